@@ -0,0 +1,989 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*
+ * Golden image tests of hdr_merge.rs on the host. Each test case renders a short sequence of
+ * synthetic YUV_420_888 or P010 frames of the same scene at the exposures of a bracket, runs them
+ * through the kernels in the same order as ViewfinderProcessor, and compares the RGBA output
+ * of the last frame against a checked-in golden image.
+ *
+ * Usage: hdr_merge_test [--update] [case...]
+ *   --update  rewrite the golden images from the current kernels instead of comparing
+ */
+
+#include "rs_host.h"
+
+#include <cmath>
+#include <cstdio>
+#include <cstring>
+#include <string>
+#include <vector>
+
+namespace rs {
+namespace hdr_merge {
+#include "hdr_merge.rs"
+}
+}
+
+namespace script = rs::hdr_merge;
+
+namespace {
+
+const uint32_t WIDTH = 96;
+const uint32_t HEIGHT = 64;
+
+// Largest difference of an output channel from its golden value that still passes, to absorb
+// differences in the last bit of the math library between hosts
+const int TOLERANCE = 1;
+
+// Render modes, in the same order as ViewfinderProcessor.MODE_ ints and the activity's modes
+const int MODE_NORMAL = 0;
+const int MODE_SPLIT = 1;
+const int MODE_HDR = 2;
+
+const int ALIGN_CANDIDATES = 2 * ALIGN_SEARCH_RADIUS + 1;
+const int ALIGN_REFINE_CANDIDATES = 2 * ALIGN_REFINE_RADIUS + 1;
+
+const std::vector<float> AUTO_EXPOSURE_BRACKET = {1.f};
+
+struct TestCase {
+    const char* name;
+    int renderMode = MODE_HDR;
+    int mergeMode = MERGE_FUSION;
+    int toneMapOperator = TONEMAP_REINHARD;
+    // Exposures of the bracket; the frames cycle through them, starting with the first
+    std::vector<float> bracket = {0.5f, 4.f};
+    // Number of frames captured before the output is compared
+    int frames = 2;
+    // A frame that never reaches the processor, as when it falls behind the camera
+    int dropped = -1;
+    bool align = false;
+    bool deghost = false;
+    bool deghostDebug = false;
+    // Move an object across the scene between frames
+    bool moving = false;
+    // Move the camera between frames
+    bool shaking = false;
+    // Standard deviation of the sensor noise added to each frame, in 8-bit luma steps
+    float noise = 0.f;
+    bool chromaDenoise = false;
+    bool temporalDenoise = false;
+    // Deliver limited range frames, converted with the BT.709 matrix instead of full range
+    // BT.601
+    bool bt709 = false;
+    // Split-screen geometry, as passed to ViewfinderProcessor
+    int splitShape = SPLIT_VERTICAL;
+    float splitX = 0.5f;
+    float splitY = 0.5f;
+    float splitAngle = 45.f;
+    float loupeRadius = 0.25f;
+    bool splitNormalize = false;
+    // Luma threshold of zebra stripes over highlights, 0 for no exposure warnings
+    float zebra = 0.f;
+    // Draw focus peaking over the edges of the current frame
+    bool focusPeaking = false;
+    // Palette of the false color map as 0xRRGGBB colors, none for the regular output
+    std::vector<uint32_t> falseColor;
+    // Interpolation of a color grading look, -1 for no color grading
+    int colorLut = -1;
+    // Unsharp mask amount, 0 for no sharpening
+    float sharpen = 0.f;
+    // Size of the ordered dither pattern of the output, 0 for no dithering
+    int dither = 0;
+    // Deliver 10-bit P010 frames instead of 8-bit YUV_420_888 ones
+    bool p010 = false;
+    // Transfer function of the frames. HLG and PQ frames are converted with the full range
+    // BT.2020 matrix, as their dataspaces are.
+    int transfer = TRANSFER_FUNCTION_GAMMA;
+};
+
+/*
+ * Same thresholds as DitherPattern.ordered()
+ */
+std::vector<float> orderedDither(int size) {
+    std::vector<float> thresholds(size * size);
+    for (int y = 0; y < size; y++) {
+        for (int x = 0; x < size; x++) {
+            int rank = 0;
+            for (int bit = 1; bit < size; bit <<= 1) {
+                rank = rank * 4 + (((x & bit) != 0 ? 2 : 0) ^ ((y & bit) != 0 ? 3 : 0));
+            }
+            thresholds[y * size + x] = (rank + 0.5f) / (size * size);
+        }
+    }
+    return thresholds;
+}
+
+/*
+ * Same palette as ViewfinderProcessor.createIrePalette()
+ */
+std::vector<uint32_t> irePalette() {
+    std::vector<uint32_t> colors(256);
+    for (uint32_t i = 0; i < colors.size(); i++) {
+        float ire = 100.f * i / (colors.size() - 1);
+        if (ire < 2.5f) {
+            colors[i] = 0x800080;
+        } else if (ire < 4.f) {
+            colors[i] = 0x0000ff;
+        } else if (ire >= 38.f && ire < 42.f) {
+            colors[i] = 0x00c000;
+        } else if (ire >= 52.f && ire < 56.f) {
+            colors[i] = 0xff80c0;
+        } else if (ire >= 97.f && ire < 99.f) {
+            colors[i] = 0xffff00;
+        } else if (ire >= 99.f) {
+            colors[i] = 0xff0000;
+        } else {
+            colors[i] = i * 0x010101;
+        }
+    }
+    return colors;
+}
+
+const TestCase TEST_CASES[] = {
+    {.name = "passthrough", .renderMode = MODE_NORMAL},
+    {.name = "split", .renderMode = MODE_SPLIT},
+    {.name = "split_odd", .renderMode = MODE_SPLIT, .frames = 3},
+    {.name = "split_dropped", .renderMode = MODE_SPLIT, .frames = 4, .dropped = 2},
+    {.name = "split_normalized", .renderMode = MODE_SPLIT, .splitNormalize = true},
+    {.name = "zebra_split", .renderMode = MODE_SPLIT, .splitNormalize = true,
+            .zebra = 240.f},
+    {.name = "zebra_shadows", .renderMode = MODE_NORMAL, .bracket = {0.02f, 0.02f},
+            .zebra = 240.f},
+    {.name = "split_moved", .renderMode = MODE_SPLIT, .splitX = 0.2f},
+    {.name = "split_horizontal", .renderMode = MODE_SPLIT, .splitShape = SPLIT_HORIZONTAL,
+            .splitY = 0.7f},
+    {.name = "split_angle", .renderMode = MODE_SPLIT, .splitShape = SPLIT_ANGLE,
+            .splitAngle = 30.f},
+    {.name = "split_loupe", .renderMode = MODE_SPLIT, .splitShape = SPLIT_LOUPE,
+            .splitX = 0.6f, .splitY = 0.4f},
+    {.name = "average", .mergeMode = MERGE_AVERAGE},
+    {.name = "fusion", .mergeMode = MERGE_FUSION},
+    {.name = "saturation", .mergeMode = MERGE_SATURATION},
+    {.name = "radiance_gamma", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_GAMMA},
+    {.name = "radiance_reinhard", .mergeMode = MERGE_RADIANCE,
+            .toneMapOperator = TONEMAP_REINHARD},
+    {.name = "radiance_filmic", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_FILMIC},
+    {.name = "radiance_log", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_LOG},
+    {.name = "radiance_local", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_LOCAL},
+    {.name = "bracket_3", .mergeMode = MERGE_BRACKET, .bracket = {0.25f, 1.f, 4.f},
+            .frames = 3},
+    {.name = "bracket_5", .mergeMode = MERGE_BRACKET,
+            .bracket = {0.125f, 0.35f, 1.f, 2.8f, 8.f}, .frames = 5},
+    {.name = "bracket_local", .mergeMode = MERGE_BRACKET, .toneMapOperator = TONEMAP_LOCAL,
+            .bracket = {0.25f, 1.f, 4.f}, .frames = 3},
+    {.name = "radiance_first_frame", .mergeMode = MERGE_RADIANCE, .frames = 1},
+    {.name = "bracket_first_frames", .mergeMode = MERGE_BRACKET,
+            .bracket = {0.125f, 0.35f, 1.f, 2.8f, 8.f}, .frames = 2},
+    {.name = "deghost_fusion", .mergeMode = MERGE_FUSION, .deghost = true, .moving = true},
+    {.name = "deghost_radiance", .mergeMode = MERGE_RADIANCE, .deghost = true,
+            .moving = true},
+    {.name = "deghost_debug", .mergeMode = MERGE_FUSION, .deghost = true,
+            .deghostDebug = true, .moving = true},
+    {.name = "aligned", .mergeMode = MERGE_FUSION, .frames = 4, .align = true,
+            .shaking = true},
+    {.name = "aligned_first_frame", .mergeMode = MERGE_FUSION, .frames = 1, .align = true},
+    {.name = "aligned_dark_static", .mergeMode = MERGE_FUSION, .bracket = {0.005f, 0.01f},
+            .frames = 5, .align = true},
+    {.name = "zebra_radiance", .mergeMode = MERGE_RADIANCE, .zebra = 230.f},
+    {.name = "zebra_local", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_LOCAL,
+            .zebra = 230.f},
+    {.name = "bt709_limited", .renderMode = MODE_NORMAL, .bt709 = true},
+    {.name = "zebra_limited", .renderMode = MODE_NORMAL, .bt709 = true, .zebra = 240.f},
+    {.name = "radiance_limited", .mergeMode = MERGE_RADIANCE, .bt709 = true},
+    {.name = "zebra_radiance_limited", .mergeMode = MERGE_RADIANCE, .bt709 = true,
+            .zebra = 230.f},
+    {.name = "focus_peaking", .renderMode = MODE_NORMAL, .focusPeaking = true},
+    {.name = "focus_peaking_local", .mergeMode = MERGE_RADIANCE,
+            .toneMapOperator = TONEMAP_LOCAL, .focusPeaking = true},
+    {.name = "false_color", .renderMode = MODE_NORMAL, .falseColor = irePalette()},
+    {.name = "false_color_radiance", .mergeMode = MERGE_RADIANCE,
+            .toneMapOperator = TONEMAP_LOCAL, .falseColor = irePalette()},
+    {.name = "color_lut_trilinear", .mergeMode = MERGE_FUSION, .colorLut = LUT_TRILINEAR},
+    {.name = "color_lut_tetrahedral", .mergeMode = MERGE_FUSION, .colorLut = LUT_TETRAHEDRAL},
+    {.name = "false_color_small_palette", .renderMode = MODE_NORMAL, .bt709 = true,
+            .falseColor = {0x000080, 0x008000, 0x808080, 0xc0c000, 0xff0000}},
+    {.name = "noisy", .renderMode = MODE_NORMAL, .bracket = {1.f}, .frames = 6,
+            .moving = true, .noise = 4.f},
+    {.name = "temporal_denoise", .renderMode = MODE_NORMAL, .bracket = {1.f}, .frames = 6,
+            .moving = true, .noise = 4.f, .temporalDenoise = true},
+    {.name = "noisy_radiance", .mergeMode = MERGE_RADIANCE, .frames = 6,
+            .moving = true, .noise = 4.f},
+    {.name = "temporal_denoise_radiance", .mergeMode = MERGE_RADIANCE, .frames = 6,
+            .moving = true, .noise = 4.f, .temporalDenoise = true},
+    {.name = "temporal_denoise_bracket_5", .mergeMode = MERGE_BRACKET,
+            .bracket = {0.125f, 0.35f, 1.f, 2.8f, 8.f}, .frames = 20, .noise = 4.f,
+            .temporalDenoise = true},
+    {.name = "chroma_denoise", .renderMode = MODE_NORMAL, .bracket = {1.f}, .frames = 1,
+            .noise = 4.f, .chromaDenoise = true},
+    {.name = "denoise_radiance", .mergeMode = MERGE_RADIANCE, .frames = 6, .moving = true,
+            .noise = 4.f, .chromaDenoise = true, .temporalDenoise = true},
+    {.name = "sharpen", .renderMode = MODE_NORMAL, .sharpen = 1.f},
+    {.name = "sharpen_average", .mergeMode = MERGE_AVERAGE, .zebra = 240.f, .sharpen = 1.f},
+    {.name = "sharpen_local", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_LOCAL,
+            .zebra = 230.f, .sharpen = 1.f},
+    {.name = "sharpen_denoised", .renderMode = MODE_NORMAL, .bracket = {1.f}, .frames = 6,
+            .noise = 4.f, .chromaDenoise = true, .temporalDenoise = true, .sharpen = 1.f},
+    {.name = "dither_ordered", .mergeMode = MERGE_RADIANCE, .dither = 8},
+    {.name = "dither_ordered_next_frame", .mergeMode = MERGE_RADIANCE, .frames = 3,
+            .dither = 8},
+    {.name = "dither_color_lut", .mergeMode = MERGE_FUSION, .colorLut = LUT_TETRAHEDRAL,
+            .dither = 4},
+    {.name = "p010_passthrough", .renderMode = MODE_NORMAL, .p010 = true},
+    {.name = "p010_fusion", .mergeMode = MERGE_FUSION, .focusPeaking = true, .p010 = true},
+    {.name = "p010_radiance", .mergeMode = MERGE_RADIANCE, .toneMapOperator = TONEMAP_LOCAL,
+            .p010 = true},
+    {.name = "p010_deghost", .mergeMode = MERGE_RADIANCE, .deghost = true, .moving = true,
+            .noise = 1.f, .chromaDenoise = true, .p010 = true},
+    {.name = "p010_aligned", .mergeMode = MERGE_FUSION, .align = true, .shaking = true,
+            .p010 = true},
+    {.name = "p010_hlg", .mergeMode = MERGE_RADIANCE, .p010 = true,
+            .transfer = TRANSFER_FUNCTION_HLG},
+    {.name = "p010_hlg_normalized", .renderMode = MODE_SPLIT, .splitNormalize = true,
+            .p010 = true, .transfer = TRANSFER_FUNCTION_HLG},
+    {.name = "p010_pq", .mergeMode = MERGE_RADIANCE, .p010 = true,
+            .transfer = TRANSFER_FUNCTION_PQ},
+};
+
+/*
+ * A frame as the camera delivers it: a YUV_420_888 buffer, or for P010 the 16-bit luma plane and
+ * the interleaved U, V chroma plane, which ViewfinderProcessor copies out of the image
+ */
+struct Frame {
+    rs::Allocation yuv = rs::Allocation::createYuv(WIDTH, HEIGHT);
+    rs::Allocation lumaP010 = rs::Allocation::create<rs::ushort>(WIDTH, HEIGHT);
+    rs::Allocation chromaP010 = rs::Allocation::create<rs::ushort2>(WIDTH / 2, HEIGHT / 2);
+};
+
+/*
+ * Camera motion of a frame, in pixels
+ */
+void frameShift(const TestCase& test, int frame, int* shiftX, int* shiftY) {
+    *shiftX = test.shaking && (frame & 1) ? 3 : 0;
+    *shiftY = test.shaking && (frame & 1) ? -2 : 0;
+}
+
+/*
+ * Deterministic value in [-1, 1] for a cell of a noise texture
+ */
+float cellNoise(int x, int y) {
+    uint32_t h = (uint32_t) x * 374761393u + (uint32_t) y * 668265263u;
+    h = (h ^ (h >> 13)) * 1274126177u;
+    h ^= h >> 16;
+    return (h & 0xffff) / 32767.5f - 1.f;
+}
+
+/*
+ * Sensor noise of a pixel of a frame, with unit standard deviation
+ */
+float pixelNoise(int x, int y, int frame, int plane) {
+    return std::sqrt(3.f) * cellNoise(x + 1000 * plane, y + 1000 * frame);
+}
+
+/*
+ * Linear radiance of the synthetic scene: an eight stop horizontal ramp with a textured
+ * surface, a bright window and optionally a small moving object.
+ */
+float sceneRadiance(int x, int y, int objectX) {
+    if (objectX >= 0 && x >= objectX && x < objectX + 12 && y >= 40 && y < 52) {
+        return 0.5f;
+    }
+    float radiance = 0.02f * std::pow(2.f, 8.f * x / WIDTH);
+    radiance *= 1.f + 0.3f * cellNoise(x >> 2, y >> 2);
+    if (x >= 56 && x < 80 && y >= 8 && y < 28) {
+        radiance *= 6.f;
+    }
+    return radiance;
+}
+
+/*
+ * Chroma of the synthetic scene, as (U, V) offsets from 128 at full saturation
+ */
+void sceneChroma(int x, int y, int objectX, float* u, float* v) {
+    if (objectX >= 0 && x >= objectX && x < objectX + 12 && y >= 40 && y < 52) {
+        *u = -40.f;
+        *v = 90.f;
+        return;
+    }
+    float hue = 2.f * (float) M_PI * y / HEIGHT + 0.02f * x;
+    *u = 60.f * std::cos(hue);
+    *v = 60.f * std::sin(hue);
+}
+
+uint8_t toByte(float value) {
+    return (uint8_t) std::fmin(std::fmax(value + 0.5f, 0.f), 255.f);
+}
+
+/*
+ * P010 sample of a value in the 8-bit range, quantized to 10 bits in the top of 16
+ */
+rs::ushort toP010(float value) {
+    return (rs::ushort) std::fmin(std::fmax(value * 4.f + 0.5f, 0.f), 1023.f) << 6;
+}
+
+/*
+ * Luma in the 8-bit range of a linear value, with reference white at 1, encoded with the
+ * transfer function of a test case and clipped to the largest value it can encode
+ */
+float encodeLuma(const TestCase& test, float linear) {
+    if (test.transfer == TRANSFER_FUNCTION_HLG) {
+        float light = std::fmin(linear * HLG_REFERENCE_WHITE, 1.f);
+        float signal = light <= 1.f / 12.f ? std::sqrt(3.f * light) :
+                HLG_A * std::log(12.f * light - HLG_B) + HLG_C;
+        return 255.f * signal;
+    }
+    if (test.transfer == TRANSFER_FUNCTION_PQ) {
+        float power = std::pow(std::fmin(linear * PQ_REFERENCE_WHITE, 1.f), PQ_M1);
+        return 255.f * std::pow((PQ_C1 + PQ_C2 * power) / (1.f + PQ_C3 * power), PQ_M2);
+    }
+    return 255.f * std::pow(std::fmin(linear, 1.f), 1.f / 2.2f);
+}
+
+/*
+ * Render one frame of a test case, as the camera would deliver it at the given exposure
+ */
+void renderFrame(const TestCase& test, int frame, float exposure, Frame* out) {
+    int shiftX;
+    int shiftY;
+    frameShift(test, frame, &shiftX, &shiftY);
+    int objectX = test.moving ? 20 + 10 * frame : -1;
+
+    for (uint32_t y = 0; y < HEIGHT; y++) {
+        for (uint32_t x = 0; x < WIDTH; x++) {
+            int sx = (int) x - shiftX;
+            int sy = (int) y - shiftY;
+            float luma = encodeLuma(test, sceneRadiance(sx, sy, objectX) * exposure);
+            float noisyLuma = luma + test.noise * pixelNoise(x, y, frame, 0);
+            if (test.bt709) {
+                noisyLuma = 16.f + noisyLuma * (219.f / 255.f);
+            }
+            if (test.p010) {
+                rs::rsSetElementAt_ushort(&out->lumaP010, toP010(noisyLuma), x, y);
+            } else {
+                out->yuv.yPlane()[y * WIDTH + x] = toByte(noisyLuma);
+            }
+
+            if ((x & 1) == 0 && (y & 1) == 0) {
+                // Chroma fades out towards clipped highlights, as on a real sensor
+                float u;
+                float v;
+                sceneChroma(sx, sy, objectX, &u, &v);
+                float saturation = 1.f - std::pow(luma / 255.f, 4.f);
+                float noisyU = 128.f + u * saturation + test.noise * pixelNoise(x, y, frame, 1);
+                float noisyV = 128.f + v * saturation + test.noise * pixelNoise(x, y, frame, 2);
+                if (test.bt709) {
+                    noisyU = 128.f + (noisyU - 128.f) * (224.f / 255.f);
+                    noisyV = 128.f + (noisyV - 128.f) * (224.f / 255.f);
+                }
+                if (test.p010) {
+                    rs::ushort2 chroma = {toP010(noisyU), toP010(noisyV)};
+                    rs::rsSetElementAt_ushort2(&out->chromaP010, chroma, x / 2, y / 2);
+                } else {
+                    uint32_t chromaIndex = (y / 2) * (WIDTH / 2) + x / 2;
+                    out->yuv.uPlane()[chromaIndex] = toByte(noisyU);
+                    out->yuv.vPlane()[chromaIndex] = toByte(noisyV);
+                }
+            }
+        }
+    }
+}
+
+/*
+ * Host counterpart of ViewfinderProcessor: owns the allocations bound to the script and runs
+ * its kernels for each frame the same way as ProcessingTask.run().
+ */
+class Processor {
+public:
+    explicit Processor(const TestCase& test) : mTest(test) {
+        mHistory = rs::Allocation::create<rs::ushort4>(WIDTH, HEIGHT, HISTORY_LENGTH);
+        mRadiance = rs::Allocation::create<rs::float4>(WIDTH, HEIGHT);
+        mLogLumaGrid = rs::Allocation::create<float>(
+                (WIDTH + GRID_TILE_SIZE - 1) / GRID_TILE_SIZE,
+                (HEIGHT + GRID_TILE_SIZE - 1) / GRID_TILE_SIZE);
+        mMotionMask = rs::Allocation::create<rs::uchar>(WIDTH, HEIGHT);
+        mDenoisedChroma = rs::Allocation::create<rs::float2>(WIDTH / 2, HEIGHT / 2);
+        mMerged = rs::Allocation::create<rs::float4>(WIDTH, HEIGHT);
+        mMergedLuma = rs::Allocation::create<float>(WIDTH, HEIGHT);
+        mAlignCost = rs::Allocation::create<float>(ALIGN_CANDIDATES, ALIGN_CANDIDATES);
+        mAlignRefineCost = rs::Allocation::create<float>(ALIGN_REFINE_CANDIDATES,
+                ALIGN_REFINE_CANDIDATES);
+        mOutput = rs::Allocation::create<rs::uchar4>(WIDTH, HEIGHT);
+
+        // Scripts keep their globals between runs; start each test from the defaults
+        script::gMergeMode = MERGE_NONE;
+        script::gToneMapKey = 0.18f;
+        script::gToneMapWhite = 4.f;
+        script::gLocalCompression = 0.5f;
+        script::gDeghostThreshold = 0.4f;
+        std::memset(script::gSlotExposure, 0, sizeof(script::gSlotExposure));
+        std::memset(script::gSlotGain, 0, sizeof(script::gSlotGain));
+        std::memset(script::gSlotOffsetX, 0, sizeof(script::gSlotOffsetX));
+        std::memset(script::gSlotOffsetY, 0, sizeof(script::gSlotOffsetY));
+        for (int i = 0; i < HISTORY_LENGTH; i++) {
+            script::gSlotBracketIndex[i] = -1;
+        }
+
+        script::gHistory = &mHistory;
+        script::gRadiance = &mRadiance;
+        script::gLogLumaGrid = &mLogLumaGrid;
+        script::gMotionMask = &mMotionMask;
+        script::gDenoisedChroma = &mDenoisedChroma;
+        script::gMerged = &mMerged;
+        script::gMergedLuma = &mMergedLuma;
+
+        if (!test.falseColor.empty()) {
+            mFalseColorLut = rs::Allocation::create<rs::uchar4>(test.falseColor.size());
+            for (uint32_t i = 0; i < test.falseColor.size(); i++) {
+                uint32_t color = test.falseColor[i];
+                rs::uchar4 rgba;
+                rgba.r = color >> 16;
+                rgba.g = color >> 8;
+                rgba.b = color;
+                rgba.a = 255;
+                rs::rsSetElementAt_uchar4(&mFalseColorLut, rgba, i);
+            }
+        }
+
+        if (test.colorLut >= 0) {
+            createLook();
+        }
+
+        if (test.dither > 0) {
+            std::vector<float> thresholds = orderedDither(test.dither);
+            mDitherTexture = rs::Allocation::create<float>(test.dither, test.dither);
+            for (int i = 0; i < test.dither * test.dither; i++) {
+                rs::rsSetElementAt_float(&mDitherTexture, thresholds[i], i % test.dither,
+                        i / test.dither);
+            }
+        }
+
+        if (test.transfer != TRANSFER_FUNCTION_GAMMA) {
+            setColorMatrix(0.2627f, 0.0593f, false);
+        } else if (test.bt709) {
+            setColorMatrix(0.2126f, 0.0722f, true);
+        } else {
+            setColorMatrix(0.299f, 0.114f, false);
+        }
+        script::gTransferFunction = test.transfer;
+    }
+
+    const rs::Allocation& process(Frame* input, int bracketIndex) {
+        // Outside HDR and split-screen mode the camera runs auto-exposed, and all frames count
+        // as the same exposure
+        const std::vector<float>& bracket =
+                mTest.renderMode == MODE_NORMAL ? AUTO_EXPOSURE_BRACKET : mTest.bracket;
+        if (mTest.renderMode == MODE_NORMAL) bracketIndex = 0;
+        int slot = mFrameCounter++ % HISTORY_LENGTH;
+        script::gSlotBracketIndex[slot] = bracketIndex;
+        script::gSlotExposure[slot] = bracket[bracketIndex];
+        script::gSlotGain[slot] = 1.f;
+        script::gHistorySlot = slot;
+        script::gBracketLength = bracket.size();
+        script::gReferenceScale = referenceScale(bracket);
+        script::gFrameCounter = mFrameCounter - 1;
+        script::gInputP010 = mTest.p010 ? 1 : 0;
+        script::gCurrentFrame = &input->yuv;
+        script::gCurrentLumaP010 = &input->lumaP010;
+        script::gCurrentChromaP010 = &input->chromaP010;
+        script::gChromaDenoise = mTest.chromaDenoise ? 1 : 0;
+        script::gChromaDenoiseRadius = 2;
+        script::gChromaDenoiseStrength = 1.f;
+        script::gTemporalDenoise = mTest.temporalDenoise ? 1 : 0;
+        script::gTemporalDenoiseStrength = 0.8f;
+        script::gTemporalDenoiseNoise = 4.f;
+        script::gSharpen = mTest.sharpen > 0.f ? 1 : 0;
+        script::gSharpenRadius = 1.f;
+        script::gSharpenAmount = mTest.sharpen;
+        script::gSharpenThreshold = 2.f;
+        script::gZebra = mTest.zebra > 0.f ? 1 : 0;
+        script::gZebraHighThreshold = mTest.zebra;
+        script::gZebraLowThreshold = 8.f;
+        script::gFocusPeaking = mTest.focusPeaking ? 1 : 0;
+        script::gFocusPeakingThreshold = 48.f;
+        script::gFocusPeakingColor = {255.f, 0.f, 0.f};
+        script::gFalseColor = mTest.falseColor.empty() ? 0 : 1;
+        script::gFalseColorLut = &mFalseColorLut;
+        script::gColorGrading = mTest.colorLut >= 0 ? 1 : 0;
+        script::gColorLut = &mColorLut;
+        script::gColorLutInterpolation = mTest.colorLut;
+        script::gDither = mTest.dither > 0 ? 1 : 0;
+        script::gDitherTexture = &mDitherTexture;
+        script::gSplitScreen = mTest.renderMode == MODE_NORMAL ? 0 : 1;
+        if (script::gSplitScreen == 1) {
+            setSplitGeometry();
+        }
+        bool doMerge = mTest.renderMode == MODE_HDR;
+        if (doMerge) {
+            script::gMergeMode = mTest.mergeMode;
+            script::gToneMapOperator = mTest.toneMapOperator;
+            script::gDeghost = mTest.deghost ? 1 : 0;
+            script::gDeghostDebug = mTest.deghostDebug ? 1 : 0;
+        } else {
+            script::gMergeMode = MERGE_NONE;
+        }
+
+        if (doMerge && mTest.align) {
+            updateSlotOffsets(slot);
+        } else {
+            std::memset(script::gSlotOffsetX, 0, sizeof(script::gSlotOffsetX));
+            std::memset(script::gSlotOffsetY, 0, sizeof(script::gSlotOffsetY));
+        }
+
+        if (mTest.chromaDenoise) {
+            rs::rsHostForEach(script::denoiseChroma, &mDenoisedChroma);
+        }
+
+        if (doMerge && mTest.deghost) {
+            rs::rsHostForEach(script::detectMotion, &mMotionMask);
+        }
+
+        rs::rsHostForEach(script::mergeHdrFrames, &mOutput);
+
+        bool radianceMerge = mTest.mergeMode == MERGE_RADIANCE ||
+                mTest.mergeMode == MERGE_BRACKET;
+        if (doMerge && radianceMerge && mTest.toneMapOperator == TONEMAP_LOCAL) {
+            rs::rsHostForEach(script::buildLuminanceGrid, &mLogLumaGrid);
+            rs::rsHostForEach(script::localToneMap, &mRadiance, &mOutput);
+        }
+
+        if (mTest.sharpen > 0.f) {
+            rs::rsHostForEach(script::sharpenLuma, &mMerged, &mOutput);
+        }
+
+        mHistograms.assign(HISTOGRAM_COUNT * HISTOGRAM_BINS, 0);
+        script::gHistogramCounts = mHistograms.data();
+        rs::rsHostForEach(script::countHistograms, &mOutput);
+        return mOutput;
+    }
+
+    const std::vector<uint32_t>& histograms() const {
+        return mHistograms;
+    }
+
+private:
+    void setSplitGeometry() {
+        float angle = mTest.splitAngle * (float) M_PI / 180.f;
+        script::gSplitShape = mTest.splitShape;
+        script::gSplitX = (int) std::lround(mTest.splitX * WIDTH);
+        script::gSplitY = (int) std::lround(mTest.splitY * HEIGHT);
+        script::gSplitNormal = {std::cos(angle), std::sin(angle)};
+        script::gLoupeRadius = mTest.loupeRadius * HEIGHT;
+        script::gSplitNormalize = mTest.splitNormalize ? 1 : 0;
+    }
+
+    /*
+     * A coarse color grading table with a contrast curve and warm shadows, nonlinear enough for
+     * the interpolation methods to differ, spanning a domain slightly wider than 0 to 1
+     */
+    void createLook() {
+        const int size = 9;
+        const float domainMin = -0.05f;
+        const float domainMax = 1.05f;
+        mColorLut = rs::Allocation::create<rs::float4>(size, size, size);
+        for (int b = 0; b < size; b++) {
+            for (int g = 0; g < size; g++) {
+                for (int r = 0; r < size; r++) {
+                    rs::float4 in = {(float) r, (float) g, (float) b, 0.f};
+                    in = in / (size - 1.f) * (domainMax - domainMin) + domainMin;
+                    rs::float4 color;
+                    for (int c = 0; c < 3; c++) {
+                        float x = std::fmin(std::fmax(in.s[c], 0.f), 1.f);
+                        color.s[c] = x * x * (3.f - 2.f * x);
+                    }
+                    float luma = 0.3f * color.r + 0.6f * color.g + 0.1f * color.b;
+                    float shadow = 0.1f * (1.f - luma) * (1.f - luma);
+                    color.r += shadow;
+                    color.b -= shadow;
+                    color.a = 1.f;
+                    rs::rsSetElementAt_float4(&mColorLut, color, r, g, b);
+                }
+            }
+        }
+        script::gColorLutDomainMin = {domainMin, domainMin, domainMin};
+        script::gColorLutDomainMax = {domainMax, domainMax, domainMax};
+    }
+
+    void setColorMatrix(float kr, float kb, bool limitedRange) {
+        float kg = 1.f - kr - kb;
+        script::gColorMatrixRV = 2.f * (1.f - kr);
+        script::gColorMatrixGU = -2.f * kb * (1.f - kb) / kg;
+        script::gColorMatrixGV = -2.f * kr * (1.f - kr) / kg;
+        script::gColorMatrixBU = 2.f * (1.f - kb);
+        script::gLimitedRange = limitedRange ? 1 : 0;
+    }
+
+    static float referenceScale(const std::vector<float>& bracket) {
+        double logSum = 0;
+        for (float exposure : bracket) {
+            logSum += std::log(exposure);
+        }
+        return (float) std::exp(logSum / bracket.size());
+    }
+
+    void updateSlotOffsets(int currentSlot) {
+        // A coarse search around no motion, refined around its best candidate. Where nothing
+        // can be compared, the previous frame is taken not to have moved.
+        int prevOffsetX = 0;
+        int prevOffsetY = 0;
+        if (searchAlignment(ALIGN_SEARCH_RADIUS, ALIGN_SEARCH_STEP, &mAlignCost, &prevOffsetX,
+                &prevOffsetY)) {
+            searchAlignment(ALIGN_REFINE_RADIUS, 1, &mAlignRefineCost, &prevOffsetX,
+                    &prevOffsetY);
+        }
+        for (int i = 0; i < HISTORY_LENGTH; i++) {
+            if (i == currentSlot) {
+                script::gSlotOffsetX[i] = 0;
+                script::gSlotOffsetY[i] = 0;
+            } else {
+                script::gSlotOffsetX[i] += prevOffsetX;
+                script::gSlotOffsetY[i] += prevOffsetY;
+            }
+        }
+    }
+
+    /*
+     * Same as ProcessingTask.searchAlignment()
+     */
+    static bool searchAlignment(int radius, int step, rs::Allocation* costAllocation,
+            int* offsetX, int* offsetY) {
+        script::gAlignCenterX = *offsetX;
+        script::gAlignCenterY = *offsetY;
+        script::gAlignRadius = radius;
+        script::gAlignStep = step;
+        rs::rsHostForEach(script::alignmentCost, costAllocation);
+        const float* costs = reinterpret_cast<const float*>(costAllocation->data.data());
+
+        int size = 2 * radius + 1;
+        int bestX = radius;
+        int bestY = radius;
+        for (int y = 0; y < size; y++) {
+            for (int x = 0; x < size; x++) {
+                float cost = costs[y * size + x];
+                float bestCost = costs[bestY * size + bestX];
+                int distance = (x - radius) * (x - radius) + (y - radius) * (y - radius);
+                int bestDistance = (bestX - radius) * (bestX - radius) +
+                        (bestY - radius) * (bestY - radius);
+                if (cost < bestCost || (cost == bestCost && distance < bestDistance)) {
+                    bestX = x;
+                    bestY = y;
+                }
+            }
+        }
+        if (costs[bestY * size + bestX] >= ALIGN_INVALID_COST) return false;
+
+        *offsetX += (bestX - radius) * step;
+        *offsetY += (bestY - radius) * step;
+        return true;
+    }
+
+    const TestCase& mTest;
+    int mFrameCounter = 0;
+
+    rs::Allocation mHistory;
+    rs::Allocation mRadiance;
+    rs::Allocation mLogLumaGrid;
+    rs::Allocation mMotionMask;
+    rs::Allocation mDenoisedChroma;
+    rs::Allocation mMerged;
+    rs::Allocation mMergedLuma;
+    rs::Allocation mAlignCost;
+    rs::Allocation mAlignRefineCost;
+    rs::Allocation mOutput;
+    rs::Allocation mFalseColorLut;
+    rs::Allocation mColorLut;
+    rs::Allocation mDitherTexture;
+    std::vector<uint32_t> mHistograms;
+};
+
+/*
+ * Check the histograms counted from a frame and its output against a count on the host
+ */
+bool checkHistograms(const TestCase& test, const std::vector<uint32_t>& histograms,
+        Frame& input, const rs::Allocation& output) {
+    std::vector<uint32_t> expected(HISTOGRAM_COUNT * HISTOGRAM_BINS);
+    for (uint32_t i = 0; i < WIDTH * HEIGHT; i++) {
+        const uint8_t* rgba = &output.data[i * 4];
+        int luma = (77 * rgba[0] + 150 * rgba[1] + 29 * rgba[2] + 128) >> 8;
+        float inputLuma = test.p010 ?
+                rs::rsGetElementAt_ushort(&input.lumaP010, i % WIDTH, i / WIDTH) / 256.f :
+                input.yuv.yPlane()[i];
+        if (test.bt709) {
+            inputLuma = std::fmin(std::fmax((inputLuma - 16.f) * (255.f / 219.f), 0.f), 255.f);
+        }
+        int frameLuma = (int) std::fmin(inputLuma, 255.f);
+        expected[HISTOGRAM_FRAME_LUMA * HISTOGRAM_BINS + frameLuma]++;
+        expected[HISTOGRAM_OUTPUT_LUMA * HISTOGRAM_BINS + luma]++;
+        expected[HISTOGRAM_RED * HISTOGRAM_BINS + rgba[0]]++;
+        expected[HISTOGRAM_GREEN * HISTOGRAM_BINS + rgba[1]]++;
+        expected[HISTOGRAM_BLUE * HISTOGRAM_BINS + rgba[2]]++;
+    }
+
+    for (int i = 0; i < HISTOGRAM_COUNT * HISTOGRAM_BINS; i++) {
+        if (histograms[i] != expected[i]) {
+            std::printf("FAIL %s: histogram %d counts %u pixels in bin %d, expected %u\n",
+                    test.name, i / HISTOGRAM_BINS, histograms[i], i % HISTOGRAM_BINS,
+                    expected[i]);
+            return false;
+        }
+    }
+    return true;
+}
+
+/*
+ * Golden images are stored as binary PAM files with an RGB_ALPHA tuple type
+ */
+bool writePam(const std::string& path, const rs::Allocation& image) {
+    FILE* file = std::fopen(path.c_str(), "wb");
+    if (file == nullptr) return false;
+    std::fprintf(file, "P7\nWIDTH %u\nHEIGHT %u\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\n"
+            "ENDHDR\n", image.dimX, image.dimY);
+    bool ok = std::fwrite(image.data.data(), 1, image.data.size(), file) == image.data.size();
+    return std::fclose(file) == 0 && ok;
+}
+
+bool readPam(const std::string& path, std::vector<uint8_t>* pixels) {
+    FILE* file = std::fopen(path.c_str(), "rb");
+    if (file == nullptr) return false;
+
+    unsigned width = 0;
+    unsigned height = 0;
+    unsigned depth = 0;
+    char line[128];
+    bool header = std::fgets(line, sizeof(line), file) != nullptr &&
+            std::strcmp(line, "P7\n") == 0;
+    while (header && std::fgets(line, sizeof(line), file) != nullptr &&
+            std::strcmp(line, "ENDHDR\n") != 0) {
+        std::sscanf(line, "WIDTH %u", &width);
+        std::sscanf(line, "HEIGHT %u", &height);
+        std::sscanf(line, "DEPTH %u", &depth);
+    }
+
+    bool ok = header && width == WIDTH && height == HEIGHT && depth == 4;
+    if (ok) {
+        pixels->resize(WIDTH * HEIGHT * 4);
+        ok = std::fread(pixels->data(), 1, pixels->size(), file) == pixels->size();
+    }
+    std::fclose(file);
+    return ok;
+}
+
+/*
+ * Render and process the frames of a test case, returning the output of the last one
+ */
+const rs::Allocation& processFrames(const TestCase& test, Processor* processor,
+        Frame* input) {
+    const rs::Allocation* output = nullptr;
+    for (int frame = 0; frame < test.frames; frame++) {
+        if (frame == test.dropped) continue;
+        int bracketIndex = frame % test.bracket.size();
+        renderFrame(test, frame, test.bracket[bracketIndex], input);
+        output = &processor->process(input, bracketIndex);
+    }
+    return *output;
+}
+
+/*
+ * Check that dithering only ever moves output values to the other neighboring 8-bit level,
+ * about as often as uniformly spread thresholds would, and without bias, by comparing the output
+ * with that of the same frames without dithering
+ */
+bool checkDither(const TestCase& test, const rs::Allocation& output) {
+    TestCase undithered = test;
+    undithered.dither = 0;
+    Processor processor(undithered);
+    Frame input;
+    const rs::Allocation& reference = processFrames(undithered, &processor, &input);
+
+    int changed = 0;
+    int sum = 0;
+    int values = 0;
+    for (size_t i = 0; i < output.data.size(); i++) {
+        if (i % 4 == 3) continue;
+        int difference = output.data[i] - reference.data[i];
+        if (std::abs(difference) > 1) {
+            std::printf("FAIL %s: dithering changed a value by %d\n", test.name, difference);
+            return false;
+        }
+        changed += difference != 0;
+        sum += difference;
+        values++;
+    }
+
+    // Thresholds spread over [0, 1) move a quarter of the values of a smooth image on average
+    float changedFraction = (float) changed / values;
+    float bias = (float) sum / values;
+    if (changedFraction < 0.15f || changedFraction > 0.35f || std::fabs(bias) > 0.1f) {
+        std::printf("FAIL %s: dithering changed %.1f%% of values with a bias of %.3f\n",
+                test.name, changedFraction * 100.f, bias);
+        return false;
+    }
+    return true;
+}
+
+/*
+ * Root mean square difference of the color channels of two outputs
+ */
+float rmsDifference(const rs::Allocation& a, const rs::Allocation& b) {
+    double sum = 0;
+    int values = 0;
+    for (size_t i = 0; i < a.data.size(); i++) {
+        if (i % 4 == 3) continue;
+        double difference = a.data[i] - b.data[i];
+        sum += difference * difference;
+        values++;
+    }
+    return (float) std::sqrt(sum / values);
+}
+
+/*
+ * Check that temporal denoising of a static scene brings the output closer to that of frames
+ * without noise than the output of the same noisy frames without it
+ */
+bool checkTemporalDenoise(const TestCase& test, const rs::Allocation& output) {
+    TestCase noisy = test;
+    noisy.temporalDenoise = false;
+    Processor noisyProcessor(noisy);
+    Frame noisyInput;
+    const rs::Allocation& noisyOutput = processFrames(noisy, &noisyProcessor, &noisyInput);
+
+    TestCase clean = noisy;
+    clean.noise = 0.f;
+    Processor cleanProcessor(clean);
+    Frame cleanInput;
+    const rs::Allocation& reference = processFrames(clean, &cleanProcessor, &cleanInput);
+
+    float noisyError = rmsDifference(noisyOutput, reference);
+    float denoisedError = rmsDifference(output, reference);
+
+    if (denoisedError > 0.8f * noisyError) {
+        std::printf("FAIL %s: denoising only reduced the noise from %.2f to %.2f\n", test.name,
+                noisyError, denoisedError);
+        return false;
+    }
+    return true;
+}
+
+/*
+ * Check that every earlier frame still in the history is found exactly where the camera motion
+ * put it relative to the last frame
+ */
+bool checkAlignment(const TestCase& test) {
+    int curX, curY;
+    frameShift(test, test.frames - 1, &curX, &curY);
+    int framesAgo = 0;
+    for (int frame = test.frames - 1; frame >= 0 && framesAgo < HISTORY_LENGTH; frame--) {
+        if (frame == test.dropped) continue;
+        int slot = script::historySlot(framesAgo++);
+        int shiftX, shiftY;
+        frameShift(test, frame, &shiftX, &shiftY);
+        if (script::gSlotOffsetX[slot] != shiftX - curX ||
+                script::gSlotOffsetY[slot] != shiftY - curY) {
+            std::printf("FAIL %s: frame %d aligned by (%d, %d), expected (%d, %d)\n", test.name,
+                    frame, script::gSlotOffsetX[slot], script::gSlotOffsetY[slot],
+                    shiftX - curX, shiftY - curY);
+            return false;
+        }
+    }
+    return true;
+}
+
+bool runTestCase(const TestCase& test, bool update) {
+    Processor processor(test);
+    Frame input;
+    const rs::Allocation* output = &processFrames(test, &processor, &input);
+
+    if (!checkHistograms(test, processor.histograms(), input, *output)) {
+        return false;
+    }
+
+    if (test.align && !checkAlignment(test)) {
+        return false;
+    }
+
+    // These run the frames again, so they go after the checks that look at the script's state
+    if (test.dither > 0 && !checkDither(test, *output)) {
+        return false;
+    }
+
+    if (test.temporalDenoise && !test.moving && !checkTemporalDenoise(test, *output)) {
+        return false;
+    }
+
+    std::string goldenPath = std::string(GOLDEN_DIR "/") + test.name + ".pam";
+    if (update) {
+        if (!writePam(goldenPath, *output)) {
+            std::printf("FAIL %s: can't write %s\n", test.name, goldenPath.c_str());
+            return false;
+        }
+        std::printf("UPDATED %s\n", test.name);
+        return true;
+    }
+
+    std::vector<uint8_t> golden;
+    if (!readPam(goldenPath, &golden)) {
+        std::printf("FAIL %s: can't read %s\n", test.name, goldenPath.c_str());
+        return false;
+    }
+
+    int maxDifference = 0;
+    int differentPixels = 0;
+    for (size_t i = 0; i < golden.size(); i += 4) {
+        int pixelDifference = 0;
+        for (size_t c = i; c < i + 4; c++) {
+            pixelDifference = std::max(pixelDifference, std::abs(output->data[c] - golden[c]));
+        }
+        maxDifference = std::max(maxDifference, pixelDifference);
+        if (pixelDifference > TOLERANCE) differentPixels++;
+    }
+    if (differentPixels > 0) {
+        std::string actualPath = std::string(test.name) + ".actual.pam";
+        writePam(actualPath, *output);
+        std::printf("FAIL %s: %d pixels differ, by up to %d; output written to %s\n",
+                test.name, differentPixels, maxDifference, actualPath.c_str());
+        return false;
+    }
+    std::printf("PASS %s\n", test.name);
+    return true;
+}
+
+} // namespace
+
+int main(int argc, char** argv) {
+    bool update = false;
+    std::vector<std::string> selected;
+    for (int i = 1; i < argc; i++) {
+        if (std::strcmp(argv[i], "--update") == 0) {
+            update = true;
+        } else {
+            selected.push_back(argv[i]);
+        }
+    }
+
+    int failures = 0;
+    int run = 0;
+    for (const TestCase& test : TEST_CASES) {
+        bool wanted = selected.empty();
+        for (const std::string& name : selected) {
+            wanted |= name == test.name;
+        }
+        if (!wanted) continue;
+        run++;
+        if (!runTestCase(test, update)) failures++;
+    }
+
+    std::printf("%d of %d test cases passed\n", run - failures, run);
+    return failures == 0 ? 0 : 1;
+}
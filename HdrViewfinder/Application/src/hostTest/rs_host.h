@@ -0,0 +1,414 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*
+ * Minimal host implementation of the RenderScript runtime, enough to compile the kernels in
+ * src/main/rs as plain C++ and run them on a Linux machine. It covers the vector types and
+ * swizzles, the math and conversion functions and the allocation accessors the scripts use;
+ * extend it when a script starts using a new builtin.
+ *
+ * Include a script inside a namespace nested in rs, after this header, so that its globals and
+ * kernels don't clash with the C library, and its calls resolve to the functions below:
+ *
+ *     namespace rs {
+ *     namespace hdr_merge {
+ *     #include "hdr_merge.rs"
+ *     }
+ *     }
+ */
+
+#ifndef RS_HOST_H
+#define RS_HOST_H
+
+#include <cmath>
+#include <cstdint>
+#include <cstdio>
+#include <cstdlib>
+#include <cstring>
+#include <type_traits>
+#include <vector>
+
+namespace rs {
+
+typedef unsigned char uchar;
+typedef unsigned short ushort;
+typedef unsigned int uint;
+
+/*
+ * Vector types. The swizzles used by the scripts are overlaid in a union, and s[] gives the
+ * operators and functions below uniform access to the elements. Assigning a scalar sets every
+ * element to it.
+ */
+
+#define RS_VECTOR2(T, V) \
+    struct V { \
+        typedef T elem; \
+        enum { N = 2 }; \
+        V& operator=(T v) { \
+            for (int i = 0; i < N; i++) s[i] = v; \
+            return *this; \
+        } \
+        union { \
+            struct { T x, y; }; \
+            struct { T r, g; }; \
+            T s[2]; \
+        }; \
+    };
+
+#define RS_VECTOR3(T, V, V2) \
+    struct V { \
+        typedef T elem; \
+        enum { N = 3 }; \
+        V& operator=(T v) { \
+            for (int i = 0; i < N; i++) s[i] = v; \
+            return *this; \
+        } \
+        union { \
+            struct { T x, y, z; }; \
+            struct { T r, g, b; }; \
+            struct { V2 xy; }; \
+            struct { V2 rg; }; \
+            struct { T _x; V2 yz; }; \
+            struct { T _r; V2 gb; }; \
+            T s[3]; \
+        }; \
+    };
+
+#define RS_VECTOR4(T, V, V2, V3) \
+    struct V { \
+        typedef T elem; \
+        enum { N = 4 }; \
+        V& operator=(T v) { \
+            for (int i = 0; i < N; i++) s[i] = v; \
+            return *this; \
+        } \
+        union { \
+            struct { T x, y, z, w; }; \
+            struct { T r, g, b, a; }; \
+            struct { V2 xy; V2 zw; }; \
+            struct { V2 rg; V2 ba; }; \
+            struct { T _x; V2 yz; }; \
+            struct { T _r; V2 gb; }; \
+            struct { V3 xyz; }; \
+            struct { V3 rgb; }; \
+            struct { T _x2; V3 yzw; }; \
+            struct { T _r2; V3 gba; }; \
+            T s[4]; \
+        }; \
+    };
+
+#define RS_VECTORS(T, P) \
+    RS_VECTOR2(T, P##2) \
+    RS_VECTOR3(T, P##3, P##2) \
+    RS_VECTOR4(T, P##4, P##2, P##3)
+
+RS_VECTORS(float, float)
+RS_VECTORS(int, int)
+RS_VECTORS(uint, uint)
+RS_VECTORS(short, short)
+RS_VECTORS(ushort, ushort)
+RS_VECTORS(uchar, uchar)
+
+#undef RS_VECTORS
+#undef RS_VECTOR4
+#undef RS_VECTOR3
+#undef RS_VECTOR2
+
+/*
+ * Element-wise vector arithmetic, with scalars applied to every element
+ */
+
+#define RS_VECTOR_OPERATOR(OP) \
+    template <class V> inline V operator OP(V a, const V& b) { \
+        for (int i = 0; i < V::N; i++) a.s[i] = a.s[i] OP b.s[i]; \
+        return a; \
+    } \
+    template <class V> inline V operator OP(V a, typename V::elem b) { \
+        for (int i = 0; i < V::N; i++) a.s[i] = a.s[i] OP b; \
+        return a; \
+    } \
+    template <class V> inline V operator OP(typename V::elem a, V b) { \
+        for (int i = 0; i < V::N; i++) b.s[i] = a OP b.s[i]; \
+        return b; \
+    } \
+    template <class V> inline V& operator OP##=(V& a, const V& b) { \
+        return a = a OP b; \
+    } \
+    template <class V> inline V& operator OP##=(V& a, typename V::elem b) { \
+        return a = a OP b; \
+    }
+
+RS_VECTOR_OPERATOR(+)
+RS_VECTOR_OPERATOR(-)
+RS_VECTOR_OPERATOR(*)
+RS_VECTOR_OPERATOR(/)
+
+#undef RS_VECTOR_OPERATOR
+
+template <class V> inline V operator-(V a) {
+    for (int i = 0; i < V::N; i++) a.s[i] = -a.s[i];
+    return a;
+}
+
+/*
+ * Conversions between vector types of the same length; float to integer truncates
+ */
+
+#define RS_CONVERT(V) \
+    template <class S> inline V convert_##V(const S& v) { \
+        static_assert((int) S::N == (int) V::N, \
+                "convert_" #V " needs a vector of the same length"); \
+        V r; \
+        for (int i = 0; i < V::N; i++) r.s[i] = static_cast<V::elem>(v.s[i]); \
+        return r; \
+    }
+
+#define RS_CONVERTS(P) RS_CONVERT(P##2) RS_CONVERT(P##3) RS_CONVERT(P##4)
+
+RS_CONVERTS(float)
+RS_CONVERTS(int)
+RS_CONVERTS(uint)
+RS_CONVERTS(short)
+RS_CONVERTS(ushort)
+RS_CONVERTS(uchar)
+
+#undef RS_CONVERTS
+#undef RS_CONVERT
+
+/*
+ * Math functions, for scalars and element-wise for vectors
+ */
+
+template <class T>
+using rs_scalar = typename std::enable_if<std::is_arithmetic<T>::value, T>::type;
+template <class V>
+using rs_vector = typename std::enable_if<(V::N > 1), V>::type;
+
+template <class T> inline rs_scalar<T> min(T a, T b) { return b < a ? b : a; }
+template <class T> inline rs_scalar<T> max(T a, T b) { return a < b ? b : a; }
+template <class T> inline rs_scalar<T> clamp(T v, T lo, T hi) { return min(max(v, lo), hi); }
+
+template <class V> inline rs_vector<V> min(V a, const V& b) {
+    for (int i = 0; i < V::N; i++) a.s[i] = min(a.s[i], b.s[i]);
+    return a;
+}
+template <class V> inline rs_vector<V> max(V a, const V& b) {
+    for (int i = 0; i < V::N; i++) a.s[i] = max(a.s[i], b.s[i]);
+    return a;
+}
+template <class V> inline V clamp(V v, typename V::elem lo, typename V::elem hi) {
+    for (int i = 0; i < V::N; i++) v.s[i] = clamp(v.s[i], lo, hi);
+    return v;
+}
+
+inline uint abs(int v) { return v < 0 ? -(uint) v : v; }
+
+inline float mix(float a, float b, float t) { return a + (b - a) * t; }
+template <class V> inline V mix(const V& a, const V& b, float t) { return a + (b - a) * t; }
+template <class V> inline V mix(const V& a, const V& b, const V& t) { return a + (b - a) * t; }
+
+#define RS_MATH1(F, STD) \
+    inline float F(float v) { return STD(v); } \
+    template <class V> inline V F(V v) { \
+        for (int i = 0; i < V::N; i++) v.s[i] = STD(v.s[i]); \
+        return v; \
+    }
+
+RS_MATH1(fabs, std::fabs)
+RS_MATH1(exp, std::exp)
+RS_MATH1(log, std::log)
+RS_MATH1(log2, std::log2)
+RS_MATH1(sqrt, std::sqrt)
+RS_MATH1(floor, std::floor)
+RS_MATH1(ceil, std::ceil)
+RS_MATH1(round, std::round)
+
+#undef RS_MATH1
+
+inline float pow(float v, float e) { return std::pow(v, e); }
+template <class V> inline V pow(V v, float e) {
+    for (int i = 0; i < V::N; i++) v.s[i] = std::pow(v.s[i], e);
+    return v;
+}
+
+template <class V> inline float dot(const V& a, const V& b) {
+    float sum = 0.f;
+    for (int i = 0; i < V::N; i++) sum += a.s[i] * b.s[i];
+    return sum;
+}
+template <class V> inline float length(const V& v) { return std::sqrt(dot(v, v)); }
+
+/*
+ * Allocations. Planar YUV allocations keep full resolution Y followed by quarter resolution
+ * U and V planes, like a YUV_420_888 buffer with separate chroma planes.
+ */
+
+struct Allocation {
+    uint32_t dimX = 0;
+    uint32_t dimY = 0;
+    uint32_t dimZ = 0;
+    size_t elementSize = 0;
+    bool yuv = false;
+    std::vector<uchar> data;
+
+    template <class T> static Allocation create(uint32_t x, uint32_t y = 1, uint32_t z = 1) {
+        Allocation a;
+        a.dimX = x;
+        a.dimY = y;
+        a.dimZ = z;
+        a.elementSize = sizeof(T);
+        a.data.resize(sizeof(T) * x * y * z);
+        return a;
+    }
+
+    static Allocation createYuv(uint32_t x, uint32_t y) {
+        Allocation a;
+        a.dimX = x;
+        a.dimY = y;
+        a.dimZ = 1;
+        a.elementSize = 1;
+        a.yuv = true;
+        a.data.resize(x * y + 2 * (x / 2) * (y / 2));
+        return a;
+    }
+
+    uchar* yPlane() { return data.data(); }
+    uchar* uPlane() { return yPlane() + dimX * dimY; }
+    uchar* vPlane() { return uPlane() + (dimX / 2) * (dimY / 2); }
+};
+
+typedef Allocation* rs_allocation;
+
+/*
+ * Out of range accesses are undefined on a device; on the host they abort, to catch them in
+ * tests.
+ */
+inline void rsHostCheckAccess(rs_allocation a, size_t elementSize, bool yuv,
+        uint32_t x, uint32_t y, uint32_t z) {
+    if (a == nullptr) {
+        std::fprintf(stderr, "Access to an unbound allocation\n");
+        std::abort();
+    }
+    if (a->elementSize != elementSize || a->yuv != yuv) {
+        std::fprintf(stderr, "Access to an allocation with the wrong element type\n");
+        std::abort();
+    }
+    if (x >= a->dimX || y >= a->dimY || z >= a->dimZ) {
+        std::fprintf(stderr, "Access at (%d, %d, %d) outside of a %ux%ux%u allocation\n",
+                (int) x, (int) y, (int) z, a->dimX, a->dimY, a->dimZ);
+        std::abort();
+    }
+}
+
+template <class T> inline T& rsHostElementAt(rs_allocation a, uint32_t x, uint32_t y,
+        uint32_t z) {
+    rsHostCheckAccess(a, sizeof(T), false, x, y, z);
+    return reinterpret_cast<T*>(a->data.data())[(z * a->dimY + y) * a->dimX + x];
+}
+
+inline uint32_t rsAllocationGetDimX(rs_allocation a) { return a->dimX; }
+inline uint32_t rsAllocationGetDimY(rs_allocation a) { return a->dimY; }
+inline uint32_t rsAllocationGetDimZ(rs_allocation a) { return a->dimZ; }
+
+#define RS_ELEMENT_ACCESS(T) \
+    inline T rsGetElementAt_##T(rs_allocation a, uint32_t x, uint32_t y = 0, \
+            uint32_t z = 0) { \
+        return rsHostElementAt<T>(a, x, y, z); \
+    } \
+    inline void rsSetElementAt_##T(rs_allocation a, T v, uint32_t x, uint32_t y = 0, \
+            uint32_t z = 0) { \
+        rsHostElementAt<T>(a, x, y, z) = v; \
+    }
+
+#define RS_ELEMENT_ACCESSES(T) \
+    RS_ELEMENT_ACCESS(T) RS_ELEMENT_ACCESS(T##2) RS_ELEMENT_ACCESS(T##3) \
+    RS_ELEMENT_ACCESS(T##4)
+
+RS_ELEMENT_ACCESSES(float)
+RS_ELEMENT_ACCESSES(int)
+RS_ELEMENT_ACCESSES(uint)
+RS_ELEMENT_ACCESSES(short)
+RS_ELEMENT_ACCESSES(ushort)
+RS_ELEMENT_ACCESSES(uchar)
+
+#undef RS_ELEMENT_ACCESSES
+#undef RS_ELEMENT_ACCESS
+
+inline uchar rsGetElementAtYuv_uchar_Y(rs_allocation a, uint32_t x, uint32_t y) {
+    rsHostCheckAccess(a, 1, true, x, y, 0);
+    return a->yPlane()[y * a->dimX + x];
+}
+
+inline uchar rsGetElementAtYuv_uchar_U(rs_allocation a, uint32_t x, uint32_t y) {
+    rsHostCheckAccess(a, 1, true, x, y, 0);
+    return a->uPlane()[(y / 2) * (a->dimX / 2) + x / 2];
+}
+
+inline uchar rsGetElementAtYuv_uchar_V(rs_allocation a, uint32_t x, uint32_t y) {
+    rsHostCheckAccess(a, 1, true, x, y, 0);
+    return a->vPlane()[(y / 2) * (a->dimX / 2) + x / 2];
+}
+
+/*
+ * Kernels run one cell at a time on the host, so atomics are plain operations. Like on a device,
+ * they return the old value.
+ */
+inline int32_t rsAtomicInc(volatile uint32_t* addr) {
+    uint32_t old = *addr;
+    *addr = old + 1;
+    return old;
+}
+
+/*
+ * Host equivalents of ScriptC.forEach_<kernel>, running a kernel over every cell of the
+ * output allocation in order
+ */
+
+template <class Out>
+void rsHostForEach(Out (*kernel)(uint32_t, uint32_t), rs_allocation out) {
+    for (uint32_t y = 0; y < out->dimY; y++) {
+        for (uint32_t x = 0; x < out->dimX; x++) {
+            rsHostElementAt<Out>(out, x, y, 0) = kernel(x, y);
+        }
+    }
+}
+
+template <class In, class Out>
+void rsHostForEach(Out (*kernel)(In, uint32_t, uint32_t), rs_allocation in,
+        rs_allocation out) {
+    for (uint32_t y = 0; y < out->dimY; y++) {
+        for (uint32_t x = 0; x < out->dimX; x++) {
+            rsHostElementAt<Out>(out, x, y, 0) = kernel(rsHostElementAt<In>(in, x, y, 0), x, y);
+        }
+    }
+}
+
+/*
+ * Host equivalent of ScriptC.forEach_<kernel> for kernels without an output, which only write
+ * through bound pointers, running over every cell of the input allocation in order
+ */
+template <class In>
+void rsHostForEach(void (*kernel)(In, uint32_t, uint32_t), rs_allocation in) {
+    for (uint32_t y = 0; y < in->dimY; y++) {
+        for (uint32_t x = 0; x < in->dimX; x++) {
+            kernel(rsHostElementAt<In>(in, x, y, 0), x, y);
+        }
+    }
+}
+
+} // namespace rs
+
+#endif // RS_HOST_H
@@ -0,0 +1,183 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package com.example.android.hdrviewfinder;
+
+/**
+ * Picks the exposures of the HDR bracket from the histograms of the frames captured with it.
+ *
+ * <p>The short exposure, the first of the bracket, is set so that only a small fraction of the
+ * frame is clipped. The long exposure, the last of the bracket, is set so that the darker part
+ * of the frame reaches a mid-tone. Each frame only moves its exposure part of the way towards
+ * its target, and new exposures are only reported once they are a fraction of a stop away from
+ * the ones in use, so that the bracket doesn't flicker on noise in the statistics.</p>
+ */
+public class AutoBracketController {
+
+    // Luma from which a pixel counts as clipped, and the fraction of the short exposure's
+    // pixels allowed to be clipped
+    private static final int CLIP_LUMA = 250;
+    private static final double CLIP_FRACTION = 0.005;
+    // Luma the brightest unclipped pixels of the short exposure are brought to
+    private static final double HIGHLIGHT_TARGET_LUMA = 230;
+
+    // Fraction of the darkest pixels of the long exposure, and the luma they are brought to
+    private static final double SHADOW_FRACTION = 0.1;
+    private static final double SHADOW_TARGET_LUMA = 80;
+
+    // Approximate transfer function of the camera output, must match TRANSFER_GAMMA in
+    // hdr_merge.rs
+    private static final double TRANSFER_GAMMA = 2.2;
+
+    // Largest change of exposure a single frame asks for, in stops, so that a clipped or black
+    // frame, whose true brightness is unknown, is stepped towards range instead of guessed
+    private static final double MAX_STEP = 2;
+    // Fraction of the way to its target each frame moves the exposure
+    private static final double SMOOTHING = 0.3;
+    // Distance in stops from the reported exposure the smoothed one has to move before a new
+    // exposure is reported
+    private static final double HYSTERESIS = 0.25;
+
+    private final double mMinLogExposure;
+    private final double mMaxLogExposure;
+
+    // Smoothed exposures in log2 nanoseconds, and the last ones reported
+    private double mShortLogExposure;
+    private double mLongLogExposure;
+    private double mReportedShortLogExposure;
+    private double mReportedLongLogExposure;
+
+    /**
+     * @param minExposure the shortest exposure time the sensor supports, in nanoseconds
+     * @param maxExposure the longest exposure time to use, in nanoseconds
+     */
+    public AutoBracketController(long minExposure, long maxExposure) {
+        mMinLogExposure = log2(minExposure);
+        mMaxLogExposure = log2(maxExposure);
+    }
+
+    /**
+     * Start from a bracket, such as the one set manually before turning on automatic exposure
+     */
+    public void reset(long shortExposure, long longExposure) {
+        mShortLogExposure = clampLogExposure(log2(shortExposure));
+        mLongLogExposure = clampLogExposure(log2(longExposure));
+        mReportedShortLogExposure = mShortLogExposure;
+        mReportedLongLogExposure = Math.max(mLongLogExposure, mShortLogExposure);
+    }
+
+    public long getShortExposure() {
+        return Math.round(Math.pow(2, mReportedShortLogExposure));
+    }
+
+    public long getLongExposure() {
+        return Math.round(Math.pow(2, mReportedLongLogExposure));
+    }
+
+    /**
+     * Update the bracket from the histograms of a frame of the HDR burst
+     *
+     * @param histograms histograms of the frame, with the exposure it was captured with
+     * @param bracketLength number of exposures in the bracket
+     * @return whether the exposures to use have changed
+     */
+    public boolean update(ViewfinderProcessor.Histograms histograms, int bracketLength) {
+        if (histograms.bracketIndex < 0 || histograms.exposure <= 0) return false;
+
+        int[] luma = histograms.frameLuma;
+        int total = 0;
+        for (int count : luma) {
+            total += count;
+        }
+        double frameLogExposure = log2(histograms.exposure);
+        if (histograms.bracketIndex == 0) {
+            double step = highlightStep(luma, total);
+            mShortLogExposure += SMOOTHING * (frameLogExposure + step - mShortLogExposure);
+            mShortLogExposure = clampLogExposure(mShortLogExposure);
+        } else if (histograms.bracketIndex == bracketLength - 1) {
+            double step = shadowStep(luma, total);
+            mLongLogExposure += SMOOTHING * (frameLogExposure + step - mLongLogExposure);
+            mLongLogExposure = clampLogExposure(mLongLogExposure);
+        } else {
+            return false;
+        }
+
+        // The long exposure never gets shorter than the short one
+        double longLogExposure = Math.max(mLongLogExposure, mShortLogExposure);
+        boolean changed = false;
+        if (Math.abs(mShortLogExposure - mReportedShortLogExposure) > HYSTERESIS) {
+            mReportedShortLogExposure = mShortLogExposure;
+            changed = true;
+        }
+        if (Math.abs(longLogExposure - mReportedLongLogExposure) > HYSTERESIS) {
+            mReportedLongLogExposure = longLogExposure;
+            changed = true;
+        }
+        return changed;
+    }
+
+    /**
+     * Change of exposure in stops that brings the short exposure's highlights into range
+     */
+    private static double highlightStep(int[] luma, int total) {
+        int clipped = 0;
+        for (int i = CLIP_LUMA; i < luma.length; i++) {
+            clipped += luma[i];
+        }
+        if (clipped > CLIP_FRACTION * total) return -MAX_STEP;
+
+        // Brightest luma below which all but the allowed clipped fraction of pixels are
+        int highlight = percentile(luma, total, 1 - CLIP_FRACTION);
+        return lumaStep(highlight, HIGHLIGHT_TARGET_LUMA);
+    }
+
+    /**
+     * Change of exposure in stops that lifts the long exposure's shadows to a mid-tone
+     */
+    private static double shadowStep(int[] luma, int total) {
+        int shadow = percentile(luma, total, SHADOW_FRACTION);
+        return lumaStep(shadow, SHADOW_TARGET_LUMA);
+    }
+
+    /**
+     * Lowest luma at or below which the given fraction of the pixels are
+     */
+    private static int percentile(int[] luma, int total, double fraction) {
+        long count = 0;
+        for (int i = 0; i < luma.length; i++) {
+            count += luma[i];
+            if (count >= fraction * total) return i;
+        }
+        return luma.length - 1;
+    }
+
+    /**
+     * Change of exposure in stops that moves a luma value to a target, assuming the camera
+     * output follows a gamma curve
+     */
+    private static double lumaStep(int luma, double targetLuma) {
+        double step = TRANSFER_GAMMA * log2(targetLuma / Math.max(luma, 1));
+        return Math.max(-MAX_STEP, Math.min(MAX_STEP, step));
+    }
+
+    private double clampLogExposure(double logExposure) {
+        return Math.max(mMinLogExposure, Math.min(mMaxLogExposure, logExposure));
+    }
+
+    private static double log2(double value) {
+        return Math.log(value) / Math.log(2);
+    }
+}
@@ -21,6 +21,9 @@ import android.hardware.camera2.CameraCaptureSession;
 import android.hardware.camera2.CameraDevice;
 import android.hardware.camera2.CameraManager;
 import android.hardware.camera2.CaptureRequest;
+import android.hardware.camera2.params.DynamicRangeProfiles;
+import android.hardware.camera2.params.OutputConfiguration;
+import android.os.Build;
 import android.os.ConditionVariable;
 import android.os.Handler;
 import android.os.HandlerThread;
@@ -28,6 +31,7 @@ import android.support.annotation.NonNull;
 import android.util.Log;
 import android.view.Surface;
 
+import java.util.ArrayList;
 import java.util.List;
 
 /**
@@ -44,6 +48,9 @@ public class CameraOps {
     private CameraDevice mCameraDevice;
     private CameraCaptureSession mCameraSession;
     private List<Surface> mSurfaces;
+    // Output captured with a 10-bit dynamic range profile, if any
+    private Surface mTenBitSurface;
+    private long mTenBitProfile = DynamicRangeProfiles.STANDARD;
 
     private final ConditionVariable mCloseWaiter = new ConditionVariable();
 
@@ -122,16 +129,32 @@ public class CameraOps {
             mCameraDevice = null;
             mCameraSession = null;
             mSurfaces = null;
+            mTenBitSurface = null;
         }
     };
 
     /**
      * Set the output Surfaces, and finish configuration if otherwise ready.
      */
-    public void setSurfaces(final List<Surface> surfaces) {
+    public void setSurfaces(List<Surface> surfaces) {
+        setSurfaces(surfaces, null, DynamicRangeProfiles.STANDARD);
+    }
+
+    /**
+     * Set the output Surfaces, one of which is captured at 10 bits, and finish configuration if
+     * otherwise ready. If the camera can't configure that output, {@link
+     * CameraReadyListener#onTenBitOutputFailed()} is called instead of onCameraReady().
+     *
+     * @param tenBitSurface the output to capture at 10 bits, or null for none
+     * @param tenBitProfile the {@link DynamicRangeProfiles} profile to capture it with
+     */
+    public void setSurfaces(final List<Surface> surfaces, final Surface tenBitSurface,
+                            final long tenBitProfile) {
         mCameraHandler.post(new Runnable() {
             public void run() {
                 mSurfaces = surfaces;
+                mTenBitSurface = tenBitSurface;
+                mTenBitProfile = tenBitProfile;
                 startCameraSession();
             }
         });
@@ -192,8 +215,21 @@ public class CameraOps {
         if (mCameraDevice == null || mSurfaces == null) return;
 
         try {
-            mCameraDevice.createCaptureSession(
-                    mSurfaces, mCameraSessionListener, mCameraHandler);
+            if (mTenBitSurface != null && Build.VERSION.SDK_INT >= Build.VERSION_CODES.TIRAMISU) {
+                List<OutputConfiguration> outputs = new ArrayList<>();
+                for (Surface surface : mSurfaces) {
+                    OutputConfiguration output = new OutputConfiguration(surface);
+                    if (surface == mTenBitSurface) {
+                        output.setDynamicRangeProfile(mTenBitProfile);
+                    }
+                    outputs.add(output);
+                }
+                mCameraDevice.createCaptureSessionByOutputConfigurations(
+                        outputs, mCameraSessionListener, mCameraHandler);
+            } else {
+                mCameraDevice.createCaptureSession(
+                        mSurfaces, mCameraSessionListener, mCameraHandler);
+            }
         } catch (CameraAccessException e) {
             String errorMessage = mErrorDisplayer.getErrorString(e);
             mErrorDisplayer.showErrorDialog(errorMessage);
@@ -227,6 +263,23 @@ public class CameraOps {
 
                 @Override
                 public void onConfigureFailed(@NonNull CameraCaptureSession session) {
+                    // A camera can list a 10-bit profile and still not support it together
+                    // with the other outputs; let the caller set up 8-bit ones instead
+                    if (mTenBitSurface != null) {
+                        mSurfaces = null;
+                        mTenBitSurface = null;
+                        mReadyHandler.post(new Runnable() {
+                            public void run() {
+                                if (null == mCameraDevice) {
+                                    return;
+                                }
+
+                                mReadyListener.onTenBitOutputFailed();
+                            }
+                        });
+                        return;
+                    }
+
                     mErrorDisplayer.showErrorDialog("Unable to configure the capture session");
                     mCameraDevice.close();
                     mCameraDevice = null;
@@ -272,6 +325,12 @@ public class CameraOps {
      */
     public interface CameraReadyListener {
         void onCameraReady();
+
+        /**
+         * The session couldn't be configured with the 10-bit output, and needs to be set up
+         * again without it
+         */
+        void onTenBitOutputFailed();
     }
 
     /**
@@ -0,0 +1,154 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package com.example.android.hdrviewfinder;
+
+import java.io.BufferedReader;
+import java.io.IOException;
+import java.io.InputStream;
+import java.io.InputStreamReader;
+import java.util.Locale;
+
+/**
+ * A 3D color lookup table read from an Adobe/Resolve .cube file.
+ *
+ * <p>Only 3D tables are supported. The domain of input values is read from either DOMAIN_MIN
+ * and DOMAIN_MAX or LUT_3D_INPUT_RANGE, and the title is ignored; files with any other keyword
+ * are rejected rather than applied in a way they weren't meant to be.</p>
+ */
+public class CubeLut {
+
+    // Largest table accepted, enough for the common 17, 33 and 65 point tables
+    private static final int MAX_SIZE = 65;
+
+    /**
+     * Number of points along each axis
+     */
+    public final int size;
+    /**
+     * Output colors as RGBA floats with alpha 1, red changing fastest, then green, then blue
+     */
+    public final float[] table;
+    /**
+     * Input values mapped to the first and last points of each axis, as red, green and blue
+     */
+    public final float[] domainMin;
+    public final float[] domainMax;
+
+    private CubeLut(int size, float[] table, float[] domainMin, float[] domainMax) {
+        this.size = size;
+        this.table = table;
+        this.domainMin = domainMin;
+        this.domainMax = domainMax;
+    }
+
+    /**
+     * Parse a .cube file
+     *
+     * @throws IOException if the stream can't be read or isn't a valid 3D .cube file
+     */
+    public static CubeLut read(InputStream stream) throws IOException {
+        BufferedReader reader = new BufferedReader(new InputStreamReader(stream, "US-ASCII"));
+        int size = 0;
+        float[] table = null;
+        float[] domainMin = {0.f, 0.f, 0.f};
+        float[] domainMax = {1.f, 1.f, 1.f};
+        int points = 0;
+
+        String line;
+        int lineNumber = 0;
+        while ((line = reader.readLine()) != null) {
+            lineNumber++;
+            line = line.trim();
+            if (line.isEmpty() || line.startsWith("#")) continue;
+
+            String[] fields = line.split("\\s+");
+            String keyword = fields[0].toUpperCase(Locale.US);
+            if (keyword.equals("LUT_3D_SIZE")) {
+                size = parseInt(fields, lineNumber);
+                if (size < 2 || size > MAX_SIZE) {
+                    throw new IOException("Unsupported LUT_3D_SIZE " + size);
+                }
+                table = new float[size * size * size * 4];
+            } else if (keyword.equals("LUT_1D_SIZE")) {
+                throw new IOException("1D .cube files are not supported");
+            } else if (keyword.equals("DOMAIN_MIN")) {
+                domainMin = parseTriplet(fields, 1, lineNumber);
+            } else if (keyword.equals("DOMAIN_MAX")) {
+                domainMax = parseTriplet(fields, 1, lineNumber);
+            } else if (keyword.equals("LUT_3D_INPUT_RANGE")) {
+                // The same range for all three channels
+                float[] range = parseValues(fields, 1, 2, lineNumber);
+                domainMin = new float[] {range[0], range[0], range[0]};
+                domainMax = new float[] {range[1], range[1], range[1]};
+            } else if (keyword.equals("TITLE")) {
+                continue;
+            } else if (Character.isLetter(keyword.charAt(0))) {
+                throw new IOException("Line " + lineNumber + ": unsupported keyword " + fields[0]);
+            } else {
+                if (table == null) {
+                    throw new IOException("Line " + lineNumber + ": data before LUT_3D_SIZE");
+                }
+                if (points == size * size * size) {
+                    throw new IOException("Line " + lineNumber + ": too many points");
+                }
+                float[] rgb = parseTriplet(fields, 0, lineNumber);
+                System.arraycopy(rgb, 0, table, points * 4, 3);
+                table[points * 4 + 3] = 1.f;
+                points++;
+            }
+        }
+
+        if (table == null || points != size * size * size) {
+            throw new IOException("Expected " + size * size * size + " points, found " + points);
+        }
+        for (int i = 0; i < 3; i++) {
+            if (domainMax[i] <= domainMin[i]) {
+                throw new IOException("Empty domain");
+            }
+        }
+        return new CubeLut(size, table, domainMin, domainMax);
+    }
+
+    private static int parseInt(String[] fields, int lineNumber) throws IOException {
+        try {
+            return Integer.parseInt(fields[1]);
+        } catch (NumberFormatException | ArrayIndexOutOfBoundsException e) {
+            throw new IOException("Line " + lineNumber + ": expected an integer");
+        }
+    }
+
+    private static float[] parseTriplet(String[] fields, int start, int lineNumber)
+            throws IOException {
+        return parseValues(fields, start, 3, lineNumber);
+    }
+
+    private static float[] parseValues(String[] fields, int start, int count, int lineNumber)
+            throws IOException {
+        if (fields.length != start + count) {
+            throw new IOException("Line " + lineNumber + ": expected " + count + " values");
+        }
+        float[] values = new float[count];
+        try {
+            for (int i = 0; i < count; i++) {
+                values[i] = Float.parseFloat(fields[start + i]);
+            }
+        } catch (NumberFormatException e) {
+            throw new IOException("Line " + lineNumber + ": expected " + count + " numbers");
+        }
+        return values;
+    }
+}
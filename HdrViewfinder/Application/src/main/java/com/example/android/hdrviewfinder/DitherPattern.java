@@ -0,0 +1,168 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package com.example.android.hdrviewfinder;
+
+import java.util.Random;
+
+/**
+ * Square threshold textures for dithering the output, with values in [0, 1) stored row by row,
+ * to be tiled over the image.
+ */
+public class DitherPattern {
+
+    // Standard deviation in pixels of the Gaussian filter the void-and-cluster method measures
+    // how tightly packed the points of the pattern are with
+    private static final double BLUE_NOISE_SIGMA = 1.5;
+    // Fraction of the pixels set in the initial pattern of the void-and-cluster method
+    private static final double BLUE_NOISE_INITIAL_FRACTION = 0.1;
+    private static final long BLUE_NOISE_SEED = 1;
+
+    private DitherPattern() {
+    }
+
+    /**
+     * The Bayer ordered dither matrix of a size that is a power of 2
+     */
+    public static float[] ordered(int size) {
+        checkSize(size);
+        float[] thresholds = new float[size * size];
+        for (int y = 0; y < size; y++) {
+            for (int x = 0; x < size; x++) {
+                // Each level of the recursive 2x2 pattern 0 2 / 3 1 is a digit of the rank, the
+                // finest level the most significant one
+                int rank = 0;
+                for (int bit = 1; bit < size; bit <<= 1) {
+                    int level = ((x & bit) != 0 ? 2 : 0) ^ ((y & bit) != 0 ? 3 : 0);
+                    rank = rank * 4 + level;
+                }
+                thresholds[y * size + x] = (rank + 0.5f) / (size * size);
+            }
+        }
+        return thresholds;
+    }
+
+    /**
+     * A blue noise pattern, tileable, made with the void-and-cluster method of Ulichney. Its
+     * thresholds are spread evenly without regular structure, so it dithers without the cross
+     * hatching of an ordered pattern. Takes on the order of size^4 steps to make.
+     */
+    public static float[] blueNoise(int size) {
+        checkSize(size);
+        int pixels = size * size;
+
+        // Gaussian filter by offset, wrapping around the edges
+        float[] filter = new float[pixels];
+        for (int dy = 0; dy < size; dy++) {
+            for (int dx = 0; dx < size; dx++) {
+                int wrappedX = Math.min(dx, size - dx);
+                int wrappedY = Math.min(dy, size - dy);
+                filter[dy * size + dx] = (float) Math.exp(-(wrappedX * wrappedX +
+                        wrappedY * wrappedY) / (2 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA));
+            }
+        }
+
+        // Start from random points, and spread them out by moving the point in the tightest
+        // cluster to the largest void until it would move back to where it was
+        boolean[] points = new boolean[pixels];
+        float[] density = new float[pixels];
+        Random random = new Random(BLUE_NOISE_SEED);
+        int initialPoints = Math.max(1, (int) (pixels * BLUE_NOISE_INITIAL_FRACTION));
+        for (int count = 0; count < initialPoints; ) {
+            int pixel = random.nextInt(pixels);
+            if (!points[pixel]) {
+                setPoint(points, density, filter, size, pixel, true);
+                count++;
+            }
+        }
+        while (true) {
+            int cluster = tightestCluster(points, density);
+            setPoint(points, density, filter, size, cluster, false);
+            int largestVoid = largestVoid(points, density);
+            setPoint(points, density, filter, size, largestVoid, true);
+            if (largestVoid == cluster) break;
+        }
+
+        // The initial points rank below the rest, the most clustered of them highest; the other
+        // pixels rank in the order filling the largest void picks them
+        int[] ranks = new int[pixels];
+        boolean[] initialPattern = points.clone();
+        float[] initialDensity = density.clone();
+        for (int rank = initialPoints - 1; rank >= 0; rank--) {
+            int cluster = tightestCluster(points, density);
+            setPoint(points, density, filter, size, cluster, false);
+            ranks[cluster] = rank;
+        }
+        points = initialPattern;
+        density = initialDensity;
+        for (int rank = initialPoints; rank < pixels; rank++) {
+            int largestVoid = largestVoid(points, density);
+            setPoint(points, density, filter, size, largestVoid, true);
+            ranks[largestVoid] = rank;
+        }
+
+        float[] thresholds = new float[pixels];
+        for (int i = 0; i < pixels; i++) {
+            thresholds[i] = (ranks[i] + 0.5f) / pixels;
+        }
+        return thresholds;
+    }
+
+    private static void checkSize(int size) {
+        if (size < 1 || Integer.bitCount(size) != 1) {
+            throw new IllegalArgumentException("Unsupported size " + size);
+        }
+    }
+
+    /**
+     * Add or remove a point of the pattern, updating the filtered density of points around it
+     */
+    private static void setPoint(boolean[] points, float[] density, float[] filter, int size,
+            int pixel, boolean set) {
+        points[pixel] = set;
+        int px = pixel % size;
+        int py = pixel / size;
+        float sign = set ? 1.f : -1.f;
+        for (int y = 0; y < size; y++) {
+            int row = ((y - py + size) % size) * size;
+            for (int x = 0; x < size; x++) {
+                density[y * size + x] += sign * filter[row + (x - px + size) % size];
+            }
+        }
+    }
+
+    /**
+     * The point with the most points around it
+     */
+    private static int tightestCluster(boolean[] points, float[] density) {
+        int best = -1;
+        for (int i = 0; i < points.length; i++) {
+            if (points[i] && (best < 0 || density[i] > density[best])) best = i;
+        }
+        return best;
+    }
+
+    /**
+     * The empty pixel with the fewest points around it
+     */
+    private static int largestVoid(boolean[] points, float[] density) {
+        int best = -1;
+        for (int i = 0; i < points.length; i++) {
+            if (!points[i] && (best < 0 || density[i] < density[best])) best = i;
+        }
+        return best;
+    }
+}
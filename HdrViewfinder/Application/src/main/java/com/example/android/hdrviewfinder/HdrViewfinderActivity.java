@@ -20,6 +20,7 @@ import android.Manifest;
 import android.annotation.SuppressLint;
 import android.content.Intent;
 import android.content.pm.PackageManager;
+import android.graphics.Color;
 import android.hardware.camera2.CameraAccessException;
 import android.hardware.camera2.CameraCaptureSession;
 import android.hardware.camera2.CameraCharacteristics;
@@ -28,8 +29,11 @@ import android.hardware.camera2.CameraManager;
 import android.hardware.camera2.CaptureRequest;
 import android.hardware.camera2.CaptureResult;
 import android.hardware.camera2.TotalCaptureResult;
+import android.graphics.ImageFormat;
+import android.hardware.camera2.params.DynamicRangeProfiles;
 import android.hardware.camera2.params.StreamConfigurationMap;
 import android.net.Uri;
+import android.os.Build;
 import android.os.Bundle;
 import android.os.Handler;
 import android.os.Looper;
@@ -40,6 +44,7 @@ import android.support.design.widget.Snackbar;
 import android.support.v4.app.ActivityCompat;
 import android.support.v7.app.AppCompatActivity;
 import android.util.Log;
+import android.util.Range;
 import android.util.Size;
 import android.view.GestureDetector;
 import android.view.Menu;
@@ -51,10 +56,14 @@ import android.view.View;
 import android.widget.Button;
 import android.widget.TextView;
 
+import java.io.IOException;
+import java.io.InputStream;
 import java.util.ArrayList;
+import java.util.Arrays;
 import java.util.List;
 import java.util.Locale;
 import java.util.Objects;
+import java.util.Set;
 
 /**
  * A small demo of advanced camera functionality with the Android camera2 API.
@@ -81,7 +90,9 @@ import java.util.Objects;
  * and display subsystems generally only accept RGB data.  Therefore, after the images are
  * fused/composited, a standard YUV->RGB color transform is applied before the the data is written
  * to the output Allocation. The HDR fusion algorithm is very simple, and tends to result in
- * lower-contrast scenes, but has very few artifacts and can run very fast.</p>
+ * lower-contrast scenes, but has very few artifacts and can run very fast. Per-pixel exposure
+ * fusion, which weights each frame by its local contrast, color saturation and how well-exposed
+ * it is, is available from the options menu.</p>
  *
  * <p>Data is passed between the subsystems (camera, RenderScript, and display) using the
  * Android {@link android.view.Surface} class, which allows for zero-copy transport of large
@@ -96,6 +107,8 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
 
     private static final int REQUEST_PERMISSIONS_REQUEST_CODE = 34;
 
+    private static final int REQUEST_COLOR_LUT_CODE = 35;
+
     /**
      * View for the camera preview.
      */
@@ -114,15 +127,25 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
     // These show lengths of exposure for even frames, exposure for odd frames, and auto exposure.
     private TextView mEvenExposureText, mOddExposureText, mAutoExposureText;
 
+    /**
+     * Histograms of the viewfinder output, shown on request.
+     */
+    private HistogramView mHistogramView;
+
     private Handler mUiHandler;
 
     private CameraCharacteristics mCameraInfo;
 
     private Surface mPreviewSurface;
     private Surface mProcessingHdrSurface;
+    // Dynamic range profile the HDR burst is captured with, and whether the camera failed to
+    // configure a 10-bit one
+    private long mHdrDynamicRangeProfile = DynamicRangeProfiles.STANDARD;
+    private boolean mTenBitOutputFailed = false;
     private Surface mProcessingNormalSurface;
     CaptureRequest.Builder mHdrBuilder;
-    ArrayList<CaptureRequest> mHdrRequests = new ArrayList<>(2);
+    ArrayList<CaptureRequest> mHdrRequests = new ArrayList<>(
+            ViewfinderProcessor.MAX_BRACKET_LENGTH);
 
     CaptureRequest mPreviewRequest;
 
@@ -132,17 +155,82 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
     CameraOps mCameraOps;
 
     private int mRenderMode = ViewfinderProcessor.MODE_NORMAL;
+    private int mMergeMode = ViewfinderProcessor.MERGE_AVERAGE;
+    private int mToneMapOperator = ViewfinderProcessor.TONEMAP_REINHARD;
+    private boolean mAlign = true;
+    private int mBracketLength = 2;
+    private boolean mDeghost = false;
+    private boolean mDeghostDebug = false;
+    private boolean mChromaDenoise = false;
+    private boolean mTemporalDenoise = false;
+    private boolean mSharpen = false;
+    private int mInputDataSpace = ViewfinderProcessor.DATASPACE_UNKNOWN;
+
+    // Split-screen geometry, with the split point as a fraction of the viewfinder size and the
+    // angle of an angled split clockwise from vertical in degrees
+    private int mSplitShape = ViewfinderProcessor.SPLIT_VERTICAL;
+    private float mSplitX = 0.5f;
+    private float mSplitY = 0.5f;
+    private float mSplitAngle = 45.f;
+    private float mLoupeRadius = 0.25f;
+    private boolean mSplitNormalize = false;
+
+    // Exposure warnings, off or with highlights marked from a fraction of full scale luma
+    private boolean mZebra = false;
+    private float mZebraLevel = 1.f;
+
+    // Focus peaking, off or with in-focus edges marked in a color
+    private boolean mFocusPeaking = false;
+    private int mFocusPeakingColor = Color.RED;
+
+    private boolean mFalseColor = false;
+
+    // Color grading table loaded by the user, or null for none
+    private CubeLut mColorLut;
+    private int mColorLutInterpolation = ViewfinderProcessor.LUT_TETRAHEDRAL;
+
+    // Dithering of the output, with the threshold texture in use. The blue noise texture takes a
+    // moment to make, so it is made off the UI thread the first time and kept.
+    private static final int DITHER_OFF = 0;
+    private static final int DITHER_ORDERED = 1;
+    private static final int DITHER_BLUE_NOISE = 2;
+    private static final int ORDERED_DITHER_SIZE = 8;
+    private static final int BLUE_NOISE_SIZE = 64;
+    private int mDither = DITHER_OFF;
+    private float[] mDitherPattern;
+    private int mDitherSize;
+    private float[] mBlueNoise;
+
+    private boolean mShowHistogram = false;
+
+    // Picks the even and odd exposures from the histograms of the HDR burst, when enabled
+    private boolean mAutoBracket = false;
+    private AutoBracketController mAutoBracketController;
+
+    // What a drag on the viewfinder in split-screen mode does
+    private static final int SPLIT_DRAG_NONE = 0;
+    private static final int SPLIT_DRAG_MOVE = 1;
+    private static final int SPLIT_DRAG_ROTATE = 2;
+    private int mSplitDrag = SPLIT_DRAG_NONE;
+
+    // Distance from the split line, as a fraction of the viewfinder width, within which a drag
+    // grabs it
+    private static final float SPLIT_GRAB_DISTANCE = 0.05f;
+
+    // Luma at or below which shadows are marked as crushed by the exposure warnings
+    private static final float ZEBRA_SHADOW_LUMA = 8.f;
 
     // Durations in nanoseconds
     private static final long MICRO_SECOND = 1000;
     private static final long MILLI_SECOND = MICRO_SECOND * 1000;
     private static final long ONE_SECOND = MILLI_SECOND * 1000;
 
+    private static final int HDR_SENSITIVITY = 1600;
+    private static final long FRAME_DURATION = ONE_SECOND / 30;
+
     private long mOddExposure = ONE_SECOND / 33;
     private long mEvenExposure = ONE_SECOND / 33;
 
-    private Object mOddExposureTag = new Object();
-    private Object mEvenExposureTag = new Object();
     private Object mAutoExposureTag = new Object();
 
     @Override
@@ -163,6 +251,7 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         mEvenExposureText = (TextView) findViewById(R.id.even_exposure);
         mOddExposureText = (TextView) findViewById(R.id.odd_exposure);
         mAutoExposureText = (TextView) findViewById(R.id.auto_exposure);
+        mHistogramView = (HistogramView) findViewById(R.id.histogram);
 
         mUiHandler = new Handler(Looper.getMainLooper());
 
@@ -207,15 +296,317 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
                         .show(getSupportFragmentManager(), FRAGMENT_DIALOG);
                 break;
             }
+            case R.id.merge_average: {
+                item.setChecked(true);
+                setMergeMode(ViewfinderProcessor.MERGE_AVERAGE);
+                break;
+            }
+            case R.id.merge_fusion: {
+                item.setChecked(true);
+                setMergeMode(ViewfinderProcessor.MERGE_FUSION);
+                break;
+            }
+            case R.id.merge_saturation: {
+                item.setChecked(true);
+                setMergeMode(ViewfinderProcessor.MERGE_SATURATION);
+                break;
+            }
+            case R.id.merge_radiance: {
+                item.setChecked(true);
+                setMergeMode(ViewfinderProcessor.MERGE_RADIANCE);
+                break;
+            }
+            case R.id.merge_bracket: {
+                item.setChecked(true);
+                setMergeMode(ViewfinderProcessor.MERGE_BRACKET);
+                break;
+            }
+            case R.id.bracket_2: {
+                item.setChecked(true);
+                setBracketLength(2);
+                break;
+            }
+            case R.id.bracket_3: {
+                item.setChecked(true);
+                setBracketLength(3);
+                break;
+            }
+            case R.id.bracket_5: {
+                item.setChecked(true);
+                setBracketLength(5);
+                break;
+            }
+            case R.id.tone_map_gamma: {
+                item.setChecked(true);
+                setToneMapOperator(ViewfinderProcessor.TONEMAP_GAMMA);
+                break;
+            }
+            case R.id.tone_map_reinhard: {
+                item.setChecked(true);
+                setToneMapOperator(ViewfinderProcessor.TONEMAP_REINHARD);
+                break;
+            }
+            case R.id.tone_map_filmic: {
+                item.setChecked(true);
+                setToneMapOperator(ViewfinderProcessor.TONEMAP_FILMIC);
+                break;
+            }
+            case R.id.tone_map_log: {
+                item.setChecked(true);
+                setToneMapOperator(ViewfinderProcessor.TONEMAP_LOG);
+                break;
+            }
+            case R.id.tone_map_local: {
+                item.setChecked(true);
+                setToneMapOperator(ViewfinderProcessor.TONEMAP_LOCAL);
+                break;
+            }
+            case R.id.split_vertical: {
+                item.setChecked(true);
+                setSplitShape(ViewfinderProcessor.SPLIT_VERTICAL);
+                break;
+            }
+            case R.id.split_horizontal: {
+                item.setChecked(true);
+                setSplitShape(ViewfinderProcessor.SPLIT_HORIZONTAL);
+                break;
+            }
+            case R.id.split_angle: {
+                item.setChecked(true);
+                setSplitShape(ViewfinderProcessor.SPLIT_ANGLE);
+                break;
+            }
+            case R.id.split_loupe: {
+                item.setChecked(true);
+                setSplitShape(ViewfinderProcessor.SPLIT_LOUPE);
+                break;
+            }
+            case R.id.split_normalize: {
+                mSplitNormalize = !item.isChecked();
+                item.setChecked(mSplitNormalize);
+                if (mProcessor != null) {
+                    mProcessor.setSplitNormalization(mSplitNormalize);
+                }
+                break;
+            }
+            case R.id.zebra_off: {
+                item.setChecked(true);
+                setExposureWarnings(false, mZebraLevel);
+                break;
+            }
+            case R.id.zebra_90: {
+                item.setChecked(true);
+                setExposureWarnings(true, 0.9f);
+                break;
+            }
+            case R.id.zebra_95: {
+                item.setChecked(true);
+                setExposureWarnings(true, 0.95f);
+                break;
+            }
+            case R.id.zebra_100: {
+                item.setChecked(true);
+                setExposureWarnings(true, 1.f);
+                break;
+            }
+            case R.id.focus_peaking_off: {
+                item.setChecked(true);
+                setFocusPeaking(false, mFocusPeakingColor);
+                break;
+            }
+            case R.id.focus_peaking_red: {
+                item.setChecked(true);
+                setFocusPeaking(true, Color.RED);
+                break;
+            }
+            case R.id.focus_peaking_yellow: {
+                item.setChecked(true);
+                setFocusPeaking(true, Color.YELLOW);
+                break;
+            }
+            case R.id.focus_peaking_blue: {
+                item.setChecked(true);
+                setFocusPeaking(true, Color.BLUE);
+                break;
+            }
+            case R.id.false_color: {
+                mFalseColor = !item.isChecked();
+                item.setChecked(mFalseColor);
+                if (mProcessor != null) {
+                    mProcessor.setFalseColor(mFalseColor);
+                }
+                break;
+            }
+            case R.id.color_lut_load: {
+                Intent intent = new Intent(Intent.ACTION_OPEN_DOCUMENT);
+                intent.addCategory(Intent.CATEGORY_OPENABLE);
+                intent.setType("*/*");
+                startActivityForResult(intent, REQUEST_COLOR_LUT_CODE);
+                break;
+            }
+            case R.id.color_lut_off: {
+                setColorLut(null);
+                break;
+            }
+            case R.id.color_lut_tetrahedral: {
+                item.setChecked(!item.isChecked());
+                mColorLutInterpolation = item.isChecked() ?
+                        ViewfinderProcessor.LUT_TETRAHEDRAL : ViewfinderProcessor.LUT_TRILINEAR;
+                if (mProcessor != null) {
+                    mProcessor.setColorLutInterpolation(mColorLutInterpolation);
+                }
+                break;
+            }
+            case R.id.dither_off: {
+                item.setChecked(true);
+                setDither(DITHER_OFF);
+                break;
+            }
+            case R.id.dither_ordered: {
+                item.setChecked(true);
+                setDither(DITHER_ORDERED);
+                break;
+            }
+            case R.id.dither_blue_noise: {
+                item.setChecked(true);
+                setDither(DITHER_BLUE_NOISE);
+                break;
+            }
+            case R.id.auto_bracket: {
+                setAutoBracket(!item.isChecked());
+                item.setChecked(mAutoBracket);
+                break;
+            }
+            case R.id.show_histogram: {
+                setShowHistogram(!item.isChecked());
+                item.setChecked(mShowHistogram);
+                break;
+            }
+            case R.id.color_matrix_auto: {
+                item.setChecked(true);
+                setInputDataSpace(ViewfinderProcessor.DATASPACE_UNKNOWN);
+                break;
+            }
+            case R.id.color_matrix_jfif: {
+                item.setChecked(true);
+                setInputDataSpace(ViewfinderProcessor.DATASPACE_JFIF);
+                break;
+            }
+            case R.id.color_matrix_bt601: {
+                item.setChecked(true);
+                setInputDataSpace(ViewfinderProcessor.DATASPACE_BT601_625);
+                break;
+            }
+            case R.id.color_matrix_bt709: {
+                item.setChecked(true);
+                setInputDataSpace(ViewfinderProcessor.DATASPACE_BT709);
+                break;
+            }
+            case R.id.color_matrix_bt2020: {
+                item.setChecked(true);
+                setInputDataSpace(ViewfinderProcessor.DATASPACE_BT2020);
+                break;
+            }
+            case R.id.align: {
+                mAlign = !item.isChecked();
+                item.setChecked(mAlign);
+                if (mProcessor != null) {
+                    mProcessor.setAlignment(mAlign);
+                }
+                break;
+            }
+            case R.id.deghost: {
+                mDeghost = !item.isChecked();
+                item.setChecked(mDeghost);
+                if (mProcessor != null) {
+                    mProcessor.setDeghosting(mDeghost);
+                }
+                break;
+            }
+            case R.id.deghost_debug: {
+                mDeghostDebug = !item.isChecked();
+                item.setChecked(mDeghostDebug);
+                if (mProcessor != null) {
+                    mProcessor.setDeghostDebug(mDeghostDebug);
+                }
+                break;
+            }
+            case R.id.chroma_denoise: {
+                mChromaDenoise = !item.isChecked();
+                item.setChecked(mChromaDenoise);
+                if (mProcessor != null) {
+                    mProcessor.setChromaDenoise(mChromaDenoise);
+                }
+                break;
+            }
+            case R.id.temporal_denoise: {
+                mTemporalDenoise = !item.isChecked();
+                item.setChecked(mTemporalDenoise);
+                if (mProcessor != null) {
+                    mProcessor.setTemporalDenoise(mTemporalDenoise);
+                }
+                break;
+            }
+            case R.id.sharpen: {
+                mSharpen = !item.isChecked();
+                item.setChecked(mSharpen);
+                if (mProcessor != null) {
+                    mProcessor.setSharpening(mSharpen);
+                }
+                break;
+            }
         }
         return super.onOptionsItemSelected(item);
     }
 
+    @Override
+    protected void onActivityResult(int requestCode, int resultCode, Intent data) {
+        super.onActivityResult(requestCode, resultCode, data);
+        if (requestCode == REQUEST_COLOR_LUT_CODE && resultCode == RESULT_OK && data != null) {
+            loadColorLut(data.getData());
+        }
+    }
+
+    /**
+     * Read a .cube file off the UI thread, and grade the viewfinder with it once loaded
+     */
+    private void loadColorLut(final Uri uri) {
+        new Thread(new Runnable() {
+            @Override
+            public void run() {
+                CubeLut lut = null;
+                String error = null;
+                try (InputStream stream = getContentResolver().openInputStream(uri)) {
+                    if (stream == null) throw new IOException("No data");
+                    lut = CubeLut.read(stream);
+                } catch (IOException e) {
+                    Log.e(TAG, "Can't load color LUT " + uri, e);
+                    error = e.getMessage();
+                }
+
+                final CubeLut loadedLut = lut;
+                final String loadError = error;
+                mUiHandler.post(new Runnable() {
+                    @Override
+                    public void run() {
+                        if (loadedLut != null) {
+                            setColorLut(loadedLut);
+                        } else {
+                            showErrorDialog(getString(R.string.color_lut_error, loadError));
+                        }
+                    }
+                });
+            }
+        }, "ColorLutLoader").start();
+    }
+
     private GestureDetector.OnGestureListener mViewListener
             = new GestureDetector.SimpleOnGestureListener() {
 
         @Override
         public boolean onDown(MotionEvent e) {
+            mSplitDrag = mRenderMode == ViewfinderProcessor.MODE_SPLIT ?
+                    splitDragAt(e.getX(), e.getY()) : SPLIT_DRAG_NONE;
             return true;
         }
 
@@ -229,6 +620,12 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         public boolean onScroll(MotionEvent e1, MotionEvent e2, float distanceX, float distanceY) {
             if (mRenderMode == ViewfinderProcessor.MODE_NORMAL) return false;
 
+            if (mSplitDrag != SPLIT_DRAG_NONE) {
+                dragSplit(e2.getX(), e2.getY());
+                return true;
+            }
+            if (mAutoBracket) return false;
+
             float xPosition = e1.getAxisValue(MotionEvent.AXIS_X);
             float width = mPreviewView.getWidth();
             float height = mPreviewView.getHeight();
@@ -252,6 +649,55 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         }
     };
 
+    /**
+     * Find what a drag starting at a point of the viewfinder does to the split: the split line
+     * or the loupe can be grabbed to move it, and an angled line can also be grabbed away from
+     * its center to rotate it.
+     */
+    private int splitDragAt(float x, float y) {
+        float width = mPreviewView.getWidth();
+        float height = mPreviewView.getHeight();
+        float dx = x - mSplitX * width;
+        float dy = y - mSplitY * height;
+        float grabDistance = SPLIT_GRAB_DISTANCE * width;
+
+        switch (mSplitShape) {
+            case ViewfinderProcessor.SPLIT_HORIZONTAL:
+                return Math.abs(dy) < grabDistance ? SPLIT_DRAG_MOVE : SPLIT_DRAG_NONE;
+            case ViewfinderProcessor.SPLIT_ANGLE: {
+                double angle = Math.toRadians(mSplitAngle);
+                double lineDistance = Math.abs(dx * Math.cos(angle) + dy * Math.sin(angle));
+                if (lineDistance >= grabDistance) return SPLIT_DRAG_NONE;
+                return Math.hypot(dx, dy) < 2 * grabDistance ?
+                        SPLIT_DRAG_MOVE : SPLIT_DRAG_ROTATE;
+            }
+            case ViewfinderProcessor.SPLIT_LOUPE:
+                return Math.hypot(dx, dy) < mLoupeRadius * height ?
+                        SPLIT_DRAG_MOVE : SPLIT_DRAG_NONE;
+            default:
+                return Math.abs(dx) < grabDistance ? SPLIT_DRAG_MOVE : SPLIT_DRAG_NONE;
+        }
+    }
+
+    private void dragSplit(float x, float y) {
+        float width = mPreviewView.getWidth();
+        float height = mPreviewView.getHeight();
+
+        if (mSplitDrag == SPLIT_DRAG_ROTATE) {
+            // Turn the line to go through the touch point
+            float dx = x - mSplitX * width;
+            float dy = y - mSplitY * height;
+            mSplitAngle = (float) Math.toDegrees(Math.atan2(-dx, dy));
+        } else {
+            mSplitX = Math.max(0.f, Math.min(1.f, x / width));
+            mSplitY = Math.max(0.f, Math.min(1.f, y / height));
+        }
+        if (mProcessor != null) {
+            mProcessor.setSplitPosition(mSplitX, mSplitY);
+            mProcessor.setSplitAngle(mSplitAngle);
+        }
+    }
+
     /**
      * Show help dialogs.
      */
@@ -290,9 +736,6 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
                 /*errorDisplayer*/ this,
                 /*readyListener*/ this,
                 /*readyHandler*/ mUiHandler);
-
-            mHdrRequests.add(null);
-            mHdrRequests.add(null);
         } else {
             Log.e(TAG, "Couldn't initialize the camera");
         }
@@ -454,6 +897,147 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         }
     }
 
+    private void setMergeMode(int mergeMode) {
+        mMergeMode = mergeMode;
+        if (mProcessor != null) {
+            mProcessor.setMergeMode(mMergeMode);
+        }
+    }
+
+    private void setToneMapOperator(int operator) {
+        mToneMapOperator = operator;
+        if (mProcessor != null) {
+            mProcessor.setToneMapOperator(mToneMapOperator);
+        }
+    }
+
+    private void setSplitShape(int shape) {
+        mSplitShape = shape;
+        if (mProcessor != null) {
+            mProcessor.setSplitShape(mSplitShape);
+        }
+    }
+
+    private void setExposureWarnings(boolean enable, float level) {
+        mZebra = enable;
+        mZebraLevel = level;
+        if (mProcessor != null) {
+            mProcessor.setExposureWarnings(mZebra);
+            mProcessor.setExposureWarningThresholds(mZebraLevel * 255.f, ZEBRA_SHADOW_LUMA);
+        }
+    }
+
+    private void setFocusPeaking(boolean enable, int color) {
+        mFocusPeaking = enable;
+        mFocusPeakingColor = color;
+        if (mProcessor != null) {
+            mProcessor.setFocusPeaking(mFocusPeaking);
+            mProcessor.setFocusPeakingColor(mFocusPeakingColor);
+        }
+    }
+
+    private void setColorLut(CubeLut lut) {
+        mColorLut = lut;
+        if (mProcessor != null) {
+            mProcessor.setColorLut(mColorLut);
+        }
+    }
+
+    private void setDither(int dither) {
+        mDither = dither;
+        if (dither == DITHER_ORDERED) {
+            setDitherPattern(DitherPattern.ordered(ORDERED_DITHER_SIZE), ORDERED_DITHER_SIZE);
+        } else if (dither == DITHER_BLUE_NOISE && mBlueNoise != null) {
+            setDitherPattern(mBlueNoise, BLUE_NOISE_SIZE);
+        } else if (dither == DITHER_BLUE_NOISE) {
+            new Thread(new Runnable() {
+                @Override
+                public void run() {
+                    final float[] blueNoise = DitherPattern.blueNoise(BLUE_NOISE_SIZE);
+                    mUiHandler.post(new Runnable() {
+                        @Override
+                        public void run() {
+                            mBlueNoise = blueNoise;
+                            if (mDither == DITHER_BLUE_NOISE) {
+                                setDitherPattern(mBlueNoise, BLUE_NOISE_SIZE);
+                            }
+                        }
+                    });
+                }
+            }, "BlueNoiseGenerator").start();
+        } else {
+            setDitherPattern(null, 0);
+        }
+    }
+
+    private void setDitherPattern(float[] thresholds, int size) {
+        mDitherPattern = thresholds;
+        mDitherSize = size;
+        if (mProcessor != null) {
+            mProcessor.setDitherPattern(mDitherPattern, mDitherSize);
+        }
+    }
+
+    private void setShowHistogram(boolean show) {
+        mShowHistogram = show;
+        mHistogramView.setVisibility(mShowHistogram ? View.VISIBLE : View.GONE);
+        updateHistogramListener();
+    }
+
+    private void setAutoBracket(boolean enable) {
+        mAutoBracket = enable;
+        if (mAutoBracket && mAutoBracketController != null) {
+            mAutoBracketController.reset(Math.min(mEvenExposure, mOddExposure),
+                    Math.max(mEvenExposure, mOddExposure));
+        }
+        updateHistogramListener();
+    }
+
+    /**
+     * Only have the processor compute histograms while something uses them
+     */
+    private void updateHistogramListener() {
+        if (mProcessor != null) {
+            mProcessor.setHistogramListener(mShowHistogram || mAutoBracket ?
+                    mHistogramListener : null, mUiHandler);
+        }
+    }
+
+    /**
+     * Listener for the histograms of processed frames
+     * Invoked on UI thread
+     */
+    private ViewfinderProcessor.HistogramListener mHistogramListener =
+            new ViewfinderProcessor.HistogramListener() {
+        @Override
+        public void onHistograms(ViewfinderProcessor.Histograms histograms) {
+            if (mShowHistogram) {
+                mHistogramView.setHistograms(histograms);
+            }
+            if (mAutoBracket && mCameraOps != null &&
+                    mRenderMode != ViewfinderProcessor.MODE_NORMAL &&
+                    mAutoBracketController.update(histograms, burstLength())) {
+                mEvenExposure = mAutoBracketController.getShortExposure();
+                mOddExposure = mAutoBracketController.getLongExposure();
+                setHdrBurst();
+            }
+        }
+    };
+
+    private void setInputDataSpace(int dataSpace) {
+        mInputDataSpace = dataSpace;
+        if (mProcessor != null) {
+            mProcessor.setInputDataSpace(mInputDataSpace);
+        }
+    }
+
+    private void setBracketLength(int bracketLength) {
+        mBracketLength = bracketLength;
+        if (mCameraOps != null && mRenderMode == ViewfinderProcessor.MODE_HDR) {
+            setHdrBurst();
+        }
+    }
+
     /**
      * Configure the surfaceview and RS processing.
      */
@@ -487,15 +1071,101 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         }
         Log.i(TAG, "Resolution chosen: " + outputSize);
 
+        // Automatic exposures stay within what the sensor supports and the frame duration
+        Range<Long> exposureRange = mCameraInfo.get(
+                CameraCharacteristics.SENSOR_INFO_EXPOSURE_TIME_RANGE);
+        long minExposure = exposureRange != null ? exposureRange.getLower() : MICRO_SECOND;
+        long maxExposure = FRAME_DURATION;
+        if (exposureRange != null) {
+            maxExposure = Math.min(maxExposure, exposureRange.getUpper());
+        }
+        mAutoBracketController = new AutoBracketController(minExposure, maxExposure);
+        mAutoBracketController.reset(Math.min(mEvenExposure, mOddExposure),
+                Math.max(mEvenExposure, mOddExposure));
+
+        // The HDR burst is taken at 10 bits when the camera supports a 10-bit HDR profile and
+        // can output P010 at this size
+        mHdrDynamicRangeProfile = mTenBitOutputFailed ? DynamicRangeProfiles.STANDARD :
+                hdrDynamicRangeProfile(mCameraInfo, configs, outputSize);
+        boolean p010HdrInput = mHdrDynamicRangeProfile != DynamicRangeProfiles.STANDARD;
+        Log.i(TAG, "HDR input format: " + (p010HdrInput ? "P010" : "YUV_420_888"));
+
         // Configure processing
-        mProcessor = new ViewfinderProcessor(mRS, outputSize);
+        mProcessor = new ViewfinderProcessor(mRS, outputSize, p010HdrInput);
+        mProcessor.setMergeMode(mMergeMode);
+        mProcessor.setToneMapOperator(mToneMapOperator);
+        mProcessor.setAlignment(mAlign);
+        mProcessor.setDeghosting(mDeghost);
+        mProcessor.setDeghostDebug(mDeghostDebug);
+        mProcessor.setChromaDenoise(mChromaDenoise);
+        mProcessor.setTemporalDenoise(mTemporalDenoise);
+        mProcessor.setSharpening(mSharpen);
+        mProcessor.setInputDataSpace(mInputDataSpace);
+        mProcessor.setSplitShape(mSplitShape);
+        mProcessor.setSplitPosition(mSplitX, mSplitY);
+        mProcessor.setSplitAngle(mSplitAngle);
+        mProcessor.setLoupeRadius(mLoupeRadius);
+        mProcessor.setSplitNormalization(mSplitNormalize);
+        mProcessor.setExposureWarnings(mZebra);
+        mProcessor.setExposureWarningThresholds(mZebraLevel * 255.f, ZEBRA_SHADOW_LUMA);
+        mProcessor.setFocusPeaking(mFocusPeaking);
+        mProcessor.setFocusPeakingColor(mFocusPeakingColor);
+        mProcessor.setFalseColor(mFalseColor);
+        mProcessor.setColorLut(mColorLut);
+        mProcessor.setColorLutInterpolation(mColorLutInterpolation);
+        mProcessor.setDitherPattern(mDitherPattern, mDitherSize);
+        updateHistogramListener();
         setupProcessor();
 
+
         // Configure the output view - this will fire surfaceChanged
         mPreviewView.setAspectRatio(outputAspect);
         mPreviewView.getHolder().setFixedSize(outputSize.getWidth(), outputSize.getHeight());
     }
 
+    /**
+     * 10-bit dynamic range profile to capture the HDR burst with: HLG10, or HDR10 where the camera
+     * doesn't support HLG10. STANDARD, for 8-bit YUV_420_888 frames, if the camera has no 10-bit
+     * output or can't output P010 at the given size.
+     */
+    private long hdrDynamicRangeProfile(CameraCharacteristics info,
+            StreamConfigurationMap configs, Size size) {
+        if (Build.VERSION.SDK_INT < Build.VERSION_CODES.TIRAMISU) {
+            return DynamicRangeProfiles.STANDARD;
+        }
+        int[] capabilities = info.get(CameraCharacteristics.REQUEST_AVAILABLE_CAPABILITIES);
+        DynamicRangeProfiles profiles =
+                info.get(CameraCharacteristics.REQUEST_AVAILABLE_DYNAMIC_RANGE_PROFILES);
+        if (capabilities == null || profiles == null || !hasCapability(capabilities,
+                CameraCharacteristics.REQUEST_AVAILABLE_CAPABILITIES_DYNAMIC_RANGE_TEN_BIT) ||
+                !supportsP010(configs, size)) {
+            return DynamicRangeProfiles.STANDARD;
+        }
+        Set<Long> supported = profiles.getSupportedProfiles();
+        if (supported.contains(DynamicRangeProfiles.HLG10)) {
+            return DynamicRangeProfiles.HLG10;
+        }
+        if (supported.contains(DynamicRangeProfiles.HDR10)) {
+            return DynamicRangeProfiles.HDR10;
+        }
+        return DynamicRangeProfiles.STANDARD;
+    }
+
+    /**
+     * Whether the camera advertises P010 output at the given size
+     */
+    private static boolean supportsP010(StreamConfigurationMap configs, Size size) {
+        int[] formats = configs.getOutputFormats();
+        if (formats == null) return false;
+        for (int format : formats) {
+            if (format == ImageFormat.YCBCR_P010) {
+                Size[] sizes = configs.getOutputSizes(format);
+                return sizes != null && Arrays.asList(sizes).contains(size);
+            }
+        }
+        return false;
+    }
+
     /**
      * Once camera is open and output surfaces are ready, configure the RS processing
      * and the camera device inputs/outputs.
@@ -511,7 +1181,12 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         cameraOutputSurfaces.add(mProcessingHdrSurface);
         cameraOutputSurfaces.add(mProcessingNormalSurface);
 
-        mCameraOps.setSurfaces(cameraOutputSurfaces);
+        if (mHdrDynamicRangeProfile != DynamicRangeProfiles.STANDARD) {
+            mCameraOps.setSurfaces(cameraOutputSurfaces, mProcessingHdrSurface,
+                    mHdrDynamicRangeProfile);
+        } else {
+            mCameraOps.setSurfaces(cameraOutputSurfaces);
+        }
     }
 
     /**
@@ -519,18 +1194,59 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
      */
     public void setHdrBurst() {
 
-        mHdrBuilder.set(CaptureRequest.SENSOR_SENSITIVITY, 1600);
-        mHdrBuilder.set(CaptureRequest.SENSOR_FRAME_DURATION, ONE_SECOND / 30);
+        mHdrBuilder.set(CaptureRequest.SENSOR_SENSITIVITY, HDR_SENSITIVITY);
+        mHdrBuilder.set(CaptureRequest.SENSOR_FRAME_DURATION, FRAME_DURATION);
+
+        // Longer brackets are spaced evenly in log space between the even and odd exposures,
+        // which stay at the ends
+        int bracketLength = burstLength();
+        long[] exposures = new long[bracketLength];
+        mHdrRequests.clear();
+        for (int i = 0; i < bracketLength; i++) {
+            double position = (double) i / (bracketLength - 1);
+            exposures[i] = Math.round(
+                    mEvenExposure * Math.pow((double) mOddExposure / mEvenExposure, position));
+
+            mHdrBuilder.set(CaptureRequest.SENSOR_EXPOSURE_TIME, exposures[i]);
+            mHdrBuilder.setTag(new BracketTag(i, bracketLength));
+            mHdrRequests.add(mHdrBuilder.build());
+        }
 
-        mHdrBuilder.set(CaptureRequest.SENSOR_EXPOSURE_TIME, mEvenExposure);
-        mHdrBuilder.setTag(mEvenExposureTag);
-        mHdrRequests.set(0, mHdrBuilder.build());
+        mCameraOps.setRepeatingBurst(mHdrRequests, mCaptureCallback, mUiHandler);
 
-        mHdrBuilder.set(CaptureRequest.SENSOR_EXPOSURE_TIME, mOddExposure);
-        mHdrBuilder.setTag(mOddExposureTag);
-        mHdrRequests.set(1, mHdrBuilder.build());
+        if (mProcessor != null) {
+            mProcessor.setHdrExposures(exposures, HDR_SENSITIVITY);
+        }
+    }
 
-        mCameraOps.setRepeatingBurst(mHdrRequests, mCaptureCallback, mUiHandler);
+    /**
+     * Number of exposures in the HDR burst. Split mode compares two exposures, HDR mode merges
+     * the selected bracket length.
+     */
+    private int burstLength() {
+        return mRenderMode == ViewfinderProcessor.MODE_HDR ? mBracketLength : 2;
+    }
+
+    /**
+     * Tag of the requests of the HDR burst, identifying the exposure of the bracket they capture.
+     * The first exposure is the one set for even frames, and the last the one for odd frames.
+     */
+    private static class BracketTag {
+        final int index;
+        final int length;
+
+        BracketTag(int index, int length) {
+            this.index = index;
+            this.length = length;
+        }
+
+        boolean isEven() {
+            return index == 0;
+        }
+
+        boolean isOdd() {
+            return index == length - 1;
+        }
     }
 
     /**
@@ -540,10 +1256,33 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
     private CameraCaptureSession.CaptureCallback mCaptureCallback
             = new CameraCaptureSession.CaptureCallback() {
 
+        @Override
+        public void onCaptureStarted(@NonNull CameraCaptureSession session,
+                                     @NonNull CaptureRequest request,
+                                     long timestamp, long frameNumber) {
+            // The timestamp is also the one of the frame the processor receives, so it can tell
+            // which exposure each frame is even if some were dropped
+            Object tag = request.getTag();
+            if (tag instanceof BracketTag && mProcessor != null) {
+                mProcessor.setFrameBracketIndex(timestamp, ((BracketTag) tag).index);
+            }
+        }
+
         public void onCaptureCompleted(@NonNull CameraCaptureSession session,
                                        @NonNull CaptureRequest request,
                                        @NonNull TotalCaptureResult result) {
 
+            // Let the processor merge and meter each frame of the burst by the exposure it was
+            // actually captured with
+            Object tag = request.getTag();
+            Long timestamp = result.get(CaptureResult.SENSOR_TIMESTAMP);
+            Long frameExposure = result.get(CaptureResult.SENSOR_EXPOSURE_TIME);
+            Integer sensitivity = result.get(CaptureResult.SENSOR_SENSITIVITY);
+            if (tag instanceof BracketTag && mProcessor != null && timestamp != null &&
+                    frameExposure != null && sensitivity != null) {
+                mProcessor.setFrameExposure(timestamp, frameExposure, sensitivity);
+            }
+
             // Only update UI every so many frames
             // Use an odd number here to ensure both even and odd exposures get an occasional update
             long frameNumber = result.getFrameNumber();
@@ -566,17 +1305,16 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
                 exposureText = String.format(Locale.US, "%d ns", exposureTime);
             }
 
-            Object tag = request.getTag();
             Log.i(TAG, "Exposure: " + exposureText);
 
-            if (tag == mEvenExposureTag) {
-                mEvenExposureText.setText(exposureText);
-
-                mEvenExposureText.setEnabled(true);
-                mOddExposureText.setEnabled(true);
-                mAutoExposureText.setEnabled(false);
-            } else if (tag == mOddExposureTag) {
-                mOddExposureText.setText(exposureText);
+            if (tag instanceof BracketTag) {
+                // Exposures in the middle of longer brackets aren't shown
+                BracketTag bracketTag = (BracketTag) tag;
+                if (bracketTag.isEven()) {
+                    mEvenExposureText.setText(exposureText);
+                } else if (bracketTag.isOdd()) {
+                    mOddExposureText.setText(exposureText);
+                }
 
                 mEvenExposureText.setEnabled(true);
                 mOddExposureText.setEnabled(true);
@@ -639,6 +1377,18 @@ public class HdrViewfinderActivity extends AppCompatActivity implements
         }
     }
 
+    @Override
+    public void onTenBitOutputFailed() {
+        // Fall back to a processor taking the HDR burst as 8-bit YUV_420_888 frames. The old
+        // one lets go of the preview surface first, so that the new one can draw to it.
+        Log.w(TAG, "Camera can't capture the HDR burst at 10 bits, falling back to 8 bits");
+        mTenBitOutputFailed = true;
+        if (mProcessor != null) {
+            mProcessor.setOutputSurface(null);
+        }
+        configureSurfaces();
+    }
+
     /**
      * Utility methods
      */
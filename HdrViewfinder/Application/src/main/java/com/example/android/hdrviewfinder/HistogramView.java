@@ -0,0 +1,111 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package com.example.android.hdrviewfinder;
+
+import android.content.Context;
+import android.graphics.Canvas;
+import android.graphics.Color;
+import android.graphics.Paint;
+import android.graphics.Path;
+import android.util.AttributeSet;
+import android.view.View;
+
+/**
+ * Draws the histograms of the viewfinder output: its luma filled in grey, with the red, green
+ * and blue channels outlined over it.
+ *
+ * <p>Each histogram is scaled to fit its tallest bin.</p>
+ */
+public class HistogramView extends View {
+
+    // Copy of the latest histograms, since the processor reuses the ones it posts
+    private final ViewfinderProcessor.Histograms mHistograms =
+            new ViewfinderProcessor.Histograms();
+    private boolean mHasHistograms = false;
+
+    private final Paint mLumaPaint = new Paint();
+    private final Paint mChannelPaint = new Paint();
+    private final Path mPath = new Path();
+
+    public HistogramView(Context context, AttributeSet attrs) {
+        super(context, attrs);
+
+        mLumaPaint.setColor(Color.GRAY);
+        mLumaPaint.setStyle(Paint.Style.FILL);
+        mChannelPaint.setStyle(Paint.Style.STROKE);
+        mChannelPaint.setStrokeWidth(2.f);
+        mChannelPaint.setAntiAlias(true);
+    }
+
+    /**
+     * Show the histograms of a new frame
+     */
+    public void setHistograms(ViewfinderProcessor.Histograms histograms) {
+        mHistograms.set(histograms);
+        mHasHistograms = true;
+        invalidate();
+    }
+
+    @Override
+    protected void onDraw(Canvas canvas) {
+        if (!mHasHistograms) return;
+
+        tracePath(mHistograms.outputLuma, true);
+        canvas.drawPath(mPath, mLumaPaint);
+
+        mChannelPaint.setColor(Color.RED);
+        tracePath(mHistograms.red, false);
+        canvas.drawPath(mPath, mChannelPaint);
+        mChannelPaint.setColor(Color.GREEN);
+        tracePath(mHistograms.green, false);
+        canvas.drawPath(mPath, mChannelPaint);
+        mChannelPaint.setColor(Color.BLUE);
+        tracePath(mHistograms.blue, false);
+        canvas.drawPath(mPath, mChannelPaint);
+    }
+
+    /**
+     * Set the path to the outline of a histogram, closed along the bottom of the view to fill it
+     */
+    private void tracePath(int[] bins, boolean closed) {
+        float width = getWidth();
+        float height = getHeight();
+        int maxCount = 1;
+        for (int count : bins) {
+            maxCount = Math.max(maxCount, count);
+        }
+
+        mPath.reset();
+        for (int i = 0; i < bins.length; i++) {
+            float x = width * i / (bins.length - 1);
+            float y = height - height * bins[i] / maxCount;
+            if (i == 0) {
+                if (closed) {
+                    mPath.moveTo(x, height);
+                    mPath.lineTo(x, y);
+                } else {
+                    mPath.moveTo(x, y);
+                }
+            } else {
+                mPath.lineTo(x, y);
+            }
+        }
+        if (closed) {
+            mPath.lineTo(width, height);
+        }
+    }
+}
@@ -17,15 +17,24 @@
 package com.example.android.hdrviewfinder;
 
 import android.graphics.ImageFormat;
+import android.media.Image;
+import android.media.ImageReader;
+import android.os.Build;
 import android.os.Handler;
 import android.os.HandlerThread;
 import android.renderscript.Allocation;
 import android.renderscript.Element;
+import android.renderscript.Float2;
+import android.renderscript.Float3;
 import android.renderscript.RenderScript;
 import android.renderscript.Type;
 import android.util.Size;
 import android.view.Surface;
 
+import java.nio.ByteOrder;
+import java.nio.ShortBuffer;
+import java.util.Arrays;
+
 /**
  * Renderscript-based merger for an HDR viewfinder
  */
@@ -33,53 +42,467 @@ public class ViewfinderProcessor {
 
     private Allocation mInputHdrAllocation;
     private Allocation mInputNormalAllocation;
-    private Allocation mPrevAllocation;
+    private ImageReader mInputHdrReader;
+    private Allocation mInputLumaP010Allocation;
+    private Allocation mInputChromaP010Allocation;
+    private short[] mInputLumaP010;
+    private short[] mInputChromaP010;
+    // One row of an image plane with its pixel stride, grown to fit the planes as they arrive
+    private short[] mInputRowP010 = new short[0];
+    private Allocation mHistoryAllocation;
+    private Allocation mRadianceAllocation;
+    private Allocation mLogLumaGridAllocation;
+    private Allocation mMotionMaskAllocation;
+    private Allocation mDenoisedChromaAllocation;
+    private Allocation mMergedAllocation;
+    private Allocation mMergedLumaAllocation;
+    private Allocation mAlignCostAllocation;
+    private Allocation mAlignRefineCostAllocation;
+    private Allocation mHistogramCountAllocation;
     private Allocation mOutputAllocation;
 
     private Handler mProcessingHandler;
+    private RenderScript mRS;
     private ScriptC_hdr_merge mHdrMergeScript;
 
     public ProcessingTask mHdrTask;
     public ProcessingTask mNormalTask;
 
+    private Size mDimensions;
     private int mMode;
+    private int mMergeMode = MERGE_AVERAGE;
+
+    // Split-screen geometry: split point as a fraction of the frame size, angle of the line
+    // clockwise from vertical in degrees, and loupe radius as a fraction of the frame height
+    private int mSplitShape = SPLIT_VERTICAL;
+    private float mSplitX = 0.5f;
+    private float mSplitY = 0.5f;
+    private float mSplitAngle = 45.f;
+    private float mLoupeRadius = 0.25f;
+    private boolean mSplitNormalize = false;
+
+    // Exposure warnings, with the luma thresholds of clipped highlights and crushed shadows
+    private boolean mZebra = false;
+    private float mZebraHighThreshold = 255.f;
+    private float mZebraLowThreshold = 8.f;
+
+    // Focus peaking, with the luma gradient from which edges are marked and their color
+    private boolean mFocusPeaking = false;
+    private float mFocusPeakingThreshold = 48.f;
+    private Float3 mFocusPeakingColor = new Float3(255.f, 0.f, 0.f);
+
+    // False color exposure map, and the palette it looks luma up in
+    private boolean mFalseColor = false;
+    private Allocation mFalseColorLutAllocation;
+
+    // Color grading table, null for none, with the range of colors it spans
+    private Allocation mColorLutAllocation;
+    private Float3 mColorLutDomainMin = new Float3(0.f, 0.f, 0.f);
+    private Float3 mColorLutDomainMax = new Float3(1.f, 1.f, 1.f);
+    private int mColorLutInterpolation = LUT_TETRAHEDRAL;
+
+    private Allocation mDitherTextureAllocation;
+
+    // Exposure times in milliseconds of each frame of the HDR burst, and their analog gain
+    private float[] mBracketExposures = {1.f, 1.f};
+    private float mGain = 1.f;
+
+    // Exposure time, gain and translation relative to the current frame of each history slot
+    private float[] mSlotExposure = new float[HISTORY_LENGTH];
+    private float[] mSlotGain = new float[HISTORY_LENGTH];
+    private int[] mSlotOffsetX = new int[HISTORY_LENGTH];
+    private int[] mSlotOffsetY = new int[HISTORY_LENGTH];
+    // Index in the bracket of the exposure of each history slot, -1 while empty
+    private int[] mSlotBracketIndex = new int[HISTORY_LENGTH];
+
+    // Frames processed by either task. Both run on the processing thread and write the same
+    // history slots, so they share the count the slots are picked by.
+    private int mFrameCounter = 0;
+    // Task that processed the latest frame; a frame from the other one starts a new history
+    private ProcessingTask mLastTask;
+    // Set when the render mode or the bracket changes, so that frames taken before aren't
+    // merged as frames of the new bracket
+    private volatile boolean mHistoryStale = false;
+
+    // Bracket index of recent frames of the HDR burst by sensor timestamp, as reported by the
+    // camera when their capture starts, and the exposure time in nanoseconds and sensitivity
+    // they were captured with, once their capture result arrives; 0 until then
+    private final long[] mTaggedTimestamps = new long[TAGGED_FRAMES];
+    private final int[] mTaggedBracketIndices = new int[TAGGED_FRAMES];
+    private final long[] mTaggedExposures = new long[TAGGED_FRAMES];
+    private final int[] mTaggedSensitivities = new int[TAGGED_FRAMES];
+    private int mNextTaggedFrame = 0;
+
+    private int mToneMapOperator = TONEMAP_REINHARD;
+    private float mToneMapKey = 0.18f;
+    private float mToneMapWhite = 4.f;
+    private float mLocalCompression = 0.5f;
+
+    private boolean mDeghost = false;
+    private boolean mDeghostDebug = false;
+    private float mDeghostThreshold = 0.4f;
+
+    private boolean mChromaDenoise = false;
+    private int mChromaDenoiseRadius = 2;
+    private float mChromaDenoiseStrength = 1.f;
+
+    private boolean mTemporalDenoise = false;
+    private float mTemporalDenoiseStrength = 0.8f;
+    private float mTemporalDenoiseNoise = 4.f;
+
+    private boolean mSharpen = false;
+    private float mSharpenRadius = 1.f;
+    private float mSharpenAmount = 0.5f;
+    private float mSharpenThreshold = 2.f;
+
+    private int mInputDataSpace = DATASPACE_UNKNOWN;
+
+    private boolean mAlign = true;
+    private float[] mAlignCosts = new float[ALIGN_CANDIDATES * ALIGN_CANDIDATES];
+    private float[] mAlignRefineCosts =
+            new float[ALIGN_REFINE_CANDIDATES * ALIGN_REFINE_CANDIDATES];
+
+    private HistogramListener mHistogramListener;
+    private Handler mHistogramHandler;
+    private final int[] mHistogramCounts = new int[HISTOGRAM_COUNT * HISTOGRAM_BINS];
+    private final int[] mHistogramZeros = new int[HISTOGRAM_COUNT * HISTOGRAM_BINS];
+    // Histograms posted to the listener, reused once it's done with them; no others are read
+    // back until then
+    private final Histograms mHistograms = new Histograms();
+    private volatile boolean mHistogramsPosted = false;
+    private HistogramListener mPostedHistogramListener;
+    private final Runnable mPostHistograms = new Runnable() {
+        @Override
+        public void run() {
+            mPostedHistogramListener.onHistograms(mHistograms);
+            mHistogramsPosted = false;
+        }
+    };
 
     public final static int MODE_NORMAL = 0;
+    public final static int MODE_SPLIT = 1;
     public final static int MODE_HDR = 2;
 
+    // must match the MERGE_ defines in hdr_merge.rs
+    public final static int MERGE_NONE = 0;
+    public final static int MERGE_AVERAGE = 1;
+    public final static int MERGE_FUSION = 2;
+    public final static int MERGE_SATURATION = 3;
+    public final static int MERGE_RADIANCE = 4;
+    public final static int MERGE_BRACKET = 5;
+
+    // must match HISTORY_LENGTH in hdr_merge.rs
+    private final static int HISTORY_LENGTH = 6;
+
+    /**
+     * Longest exposure bracket that can be merged
+     */
+    public final static int MAX_BRACKET_LENGTH = HISTORY_LENGTH - 1;
+
+    // Fields of dataspace values, as in android.hardware.DataSpace
+    private final static int DATASPACE_STANDARD_SHIFT = 16;
+    private final static int DATASPACE_STANDARD_MASK = 63 << DATASPACE_STANDARD_SHIFT;
+    private final static int DATASPACE_STANDARD_BT709 = 1 << DATASPACE_STANDARD_SHIFT;
+    private final static int DATASPACE_STANDARD_BT601_625 = 2 << DATASPACE_STANDARD_SHIFT;
+    private final static int DATASPACE_STANDARD_BT601_525 = 4 << DATASPACE_STANDARD_SHIFT;
+    private final static int DATASPACE_STANDARD_BT2020 = 6 << DATASPACE_STANDARD_SHIFT;
+    private final static int DATASPACE_TRANSFER_SHIFT = 22;
+    private final static int DATASPACE_TRANSFER_MASK = 31 << DATASPACE_TRANSFER_SHIFT;
+    private final static int DATASPACE_TRANSFER_SMPTE_170M = 3 << DATASPACE_TRANSFER_SHIFT;
+    private final static int DATASPACE_TRANSFER_ST2084 = 7 << DATASPACE_TRANSFER_SHIFT;
+    private final static int DATASPACE_TRANSFER_HLG = 8 << DATASPACE_TRANSFER_SHIFT;
+    private final static int DATASPACE_RANGE_SHIFT = 27;
+    private final static int DATASPACE_RANGE_MASK = 7 << DATASPACE_RANGE_SHIFT;
+    private final static int DATASPACE_RANGE_FULL = 1 << DATASPACE_RANGE_SHIFT;
+    private final static int DATASPACE_RANGE_LIMITED = 2 << DATASPACE_RANGE_SHIFT;
+
+    /**
+     * Input dataspace that leaves the YUV to RGB conversion to the dataspace of the stream
+     */
+    public final static int DATASPACE_UNKNOWN = 0;
+
+    // Input dataspaces with a known YUV to RGB conversion, same values as in DataSpace
+    public final static int DATASPACE_JFIF = DATASPACE_STANDARD_BT601_625 |
+            DATASPACE_TRANSFER_SMPTE_170M | DATASPACE_RANGE_FULL;
+    public final static int DATASPACE_BT601_625 = DATASPACE_STANDARD_BT601_625 |
+            DATASPACE_TRANSFER_SMPTE_170M | DATASPACE_RANGE_LIMITED;
+    public final static int DATASPACE_BT709 = DATASPACE_STANDARD_BT709 |
+            DATASPACE_TRANSFER_SMPTE_170M | DATASPACE_RANGE_LIMITED;
+    public final static int DATASPACE_BT2020 = DATASPACE_STANDARD_BT2020 |
+            DATASPACE_TRANSFER_SMPTE_170M | DATASPACE_RANGE_FULL;
+
+    // must match the TRANSFER_FUNCTION_ defines in hdr_merge.rs
+    private final static int TRANSFER_FUNCTION_GAMMA = 0;
+    private final static int TRANSFER_FUNCTION_HLG = 1;
+    private final static int TRANSFER_FUNCTION_PQ = 2;
+
+    // Images of the P010 input the camera can be ahead of the processing thread by
+    private final static int P010_MAX_IMAGES = 2;
+
+    // Bracket of the auto-exposed viewfinder, whose frames all count as the same exposure
+    private final static float[] AUTO_EXPOSURE_BRACKET = {1.f};
+
+    // Number of recent frames whose bracket index is remembered, enough to cover the latency
+    // between the start of a capture and its frame reaching the processing thread
+    private final static int TAGGED_FRAMES = 16;
+
+    // must match the SPLIT_ defines in hdr_merge.rs
+    public final static int SPLIT_VERTICAL = 0;
+    public final static int SPLIT_HORIZONTAL = 1;
+    public final static int SPLIT_ANGLE = 2;
+    public final static int SPLIT_LOUPE = 3;
+
+    // must match the LUT_ defines in hdr_merge.rs
+    public final static int LUT_TRILINEAR = 0;
+    public final static int LUT_TETRAHEDRAL = 1;
+
+    // must match the TONEMAP_ defines in hdr_merge.rs
+    public final static int TONEMAP_GAMMA = 0;
+    public final static int TONEMAP_REINHARD = 1;
+    public final static int TONEMAP_FILMIC = 2;
+    public final static int TONEMAP_LOG = 3;
+    public final static int TONEMAP_LOCAL = 4;
+
+    // must match GRID_TILE_SIZE in hdr_merge.rs
+    private final static int GRID_TILE_SIZE = 32;
+
+    // must match the ALIGN_ defines in hdr_merge.rs
+    private final static int ALIGN_SEARCH_RADIUS = 8;
+    private final static int ALIGN_SEARCH_STEP = 2;
+    private final static int ALIGN_REFINE_RADIUS = 1;
+    private final static int ALIGN_CANDIDATES = 2 * ALIGN_SEARCH_RADIUS + 1;
+    private final static int ALIGN_REFINE_CANDIDATES = 2 * ALIGN_REFINE_RADIUS + 1;
+    private final static float ALIGN_INVALID_COST = 1000.f;
+
+    // must match the HISTOGRAM_ defines in hdr_merge.rs
+    public final static int HISTOGRAM_BINS = 256;
+    private final static int HISTOGRAM_FRAME_LUMA = 0;
+    private final static int HISTOGRAM_OUTPUT_LUMA = 1;
+    private final static int HISTOGRAM_RED = 2;
+    private final static int HISTOGRAM_GREEN = 3;
+    private final static int HISTOGRAM_BLUE = 4;
+    private final static int HISTOGRAM_COUNT = 5;
+    // Frames processed for each one whose histograms are read back. Odd and prime, so that
+    // over a few readbacks it lands on every exposure of a bracket of up to five.
+    public final static int HISTOGRAM_FRAME_INTERVAL = 7;
+
+    // Colors in the built-in false color palettes, one for each 8-bit luma value
+    private final static int FALSE_COLOR_LEVELS = 256;
+
+    /**
+     * Pixel counts of each 8-bit value of a processed frame
+     */
+    public static class Histograms {
+        /**
+         * Luma of the camera frame, before merging
+         */
+        public final int[] frameLuma = new int[HISTOGRAM_BINS];
+        /**
+         * BT.601 luma of the output
+         */
+        public final int[] outputLuma = new int[HISTOGRAM_BINS];
+        public final int[] red = new int[HISTOGRAM_BINS];
+        public final int[] green = new int[HISTOGRAM_BINS];
+        public final int[] blue = new int[HISTOGRAM_BINS];
+        /**
+         * Index in the HDR bracket of the exposure of the camera frame, or -1 outside of the
+         * HDR burst
+         */
+        public int bracketIndex = -1;
+        /**
+         * Exposure time the camera frame was captured with in nanoseconds, scaled to the
+         * sensitivity set for the HDR burst if it was captured with another one, or 0 outside of
+         * the HDR burst
+         */
+        public long exposure;
+
+        Histograms() {
+        }
+
+        /**
+         * Set the bins from the counts of all the histograms, as read back from the script
+         */
+        void setCounts(int[] counts) {
+            copyBins(counts, HISTOGRAM_FRAME_LUMA, frameLuma);
+            copyBins(counts, HISTOGRAM_OUTPUT_LUMA, outputLuma);
+            copyBins(counts, HISTOGRAM_RED, red);
+            copyBins(counts, HISTOGRAM_GREEN, green);
+            copyBins(counts, HISTOGRAM_BLUE, blue);
+        }
+
+        /**
+         * Copy the bins and the frame of other histograms
+         */
+        void set(Histograms histograms) {
+            System.arraycopy(histograms.frameLuma, 0, frameLuma, 0, HISTOGRAM_BINS);
+            System.arraycopy(histograms.outputLuma, 0, outputLuma, 0, HISTOGRAM_BINS);
+            System.arraycopy(histograms.red, 0, red, 0, HISTOGRAM_BINS);
+            System.arraycopy(histograms.green, 0, green, 0, HISTOGRAM_BINS);
+            System.arraycopy(histograms.blue, 0, blue, 0, HISTOGRAM_BINS);
+            bracketIndex = histograms.bracketIndex;
+            exposure = histograms.exposure;
+        }
+
+        private static void copyBins(int[] counts, int histogram, int[] bins) {
+            System.arraycopy(counts, histogram * HISTOGRAM_BINS, bins, 0, HISTOGRAM_BINS);
+        }
+    }
+
+    /**
+     * Receives the histograms of processed frames, one out of every
+     * {@link #HISTOGRAM_FRAME_INTERVAL}
+     */
+    public interface HistogramListener {
+        /**
+         * The histograms are reused for later frames once this returns, so copy what needs to be
+         * kept
+         */
+        void onHistograms(Histograms histograms);
+    }
+
     public ViewfinderProcessor(RenderScript rs, Size dimensions) {
+        this(rs, dimensions, false);
+    }
+
+    /**
+     * @param p010HdrInput take the frames of the HDR burst as 10-bit {@link
+     *     ImageFormat#YCBCR_P010} images instead of YUV_420_888, for the camera to deliver to
+     *     {@link #getInputHdrSurface()}
+     */
+    public ViewfinderProcessor(RenderScript rs, Size dimensions, boolean p010HdrInput) {
+        mRS = rs;
+        mDimensions = dimensions;
+        Arrays.fill(mSlotBracketIndex, -1);
+        Arrays.fill(mTaggedBracketIndices, -1);
+
         Type.Builder yuvTypeBuilder = new Type.Builder(rs, Element.YUV(rs));
         yuvTypeBuilder.setX(dimensions.getWidth());
         yuvTypeBuilder.setY(dimensions.getHeight());
         yuvTypeBuilder.setYuvFormat(ImageFormat.YUV_420_888);
-        mInputHdrAllocation = Allocation.createTyped(rs, yuvTypeBuilder.create(),
-                Allocation.USAGE_IO_INPUT | Allocation.USAGE_SCRIPT);
+        if (!p010HdrInput) {
+            mInputHdrAllocation = Allocation.createTyped(rs, yuvTypeBuilder.create(),
+                    Allocation.USAGE_IO_INPUT | Allocation.USAGE_SCRIPT);
+        }
         mInputNormalAllocation = Allocation.createTyped(rs, yuvTypeBuilder.create(),
                 Allocation.USAGE_IO_INPUT | Allocation.USAGE_SCRIPT);
 
+        // Recent frames are kept as 16-bit YUV, plus the contrast used by exposure fusion,
+        // one frame per Z slice
+        Type.Builder historyTypeBuilder = new Type.Builder(rs, Element.U16_4(rs));
+        historyTypeBuilder.setX(dimensions.getWidth());
+        historyTypeBuilder.setY(dimensions.getHeight());
+        historyTypeBuilder.setZ(HISTORY_LENGTH);
+        mHistoryAllocation = Allocation.createTyped(rs, historyTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
         Type.Builder rgbTypeBuilder = new Type.Builder(rs, Element.RGBA_8888(rs));
         rgbTypeBuilder.setX(dimensions.getWidth());
         rgbTypeBuilder.setY(dimensions.getHeight());
-        mPrevAllocation = Allocation.createTyped(rs, rgbTypeBuilder.create(),
-                Allocation.USAGE_SCRIPT);
         mOutputAllocation = Allocation.createTyped(rs, rgbTypeBuilder.create(),
                 Allocation.USAGE_IO_OUTPUT | Allocation.USAGE_SCRIPT);
 
+        Type.Builder radianceTypeBuilder = new Type.Builder(rs, Element.F32_4(rs));
+        radianceTypeBuilder.setX(dimensions.getWidth());
+        radianceTypeBuilder.setY(dimensions.getHeight());
+        mRadianceAllocation = Allocation.createTyped(rs, radianceTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
+        // Merged YUV output before sharpening, in the same layout as the radiance merge
+        mMergedAllocation = Allocation.createTyped(rs, radianceTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
+        // Luma of the merged output alone, which is what sharpening reads around each pixel
+        Type.Builder lumaTypeBuilder = new Type.Builder(rs, Element.F32(rs));
+        lumaTypeBuilder.setX(dimensions.getWidth());
+        lumaTypeBuilder.setY(dimensions.getHeight());
+        mMergedLumaAllocation = Allocation.createTyped(rs, lumaTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
+        Type.Builder gridTypeBuilder = new Type.Builder(rs, Element.F32(rs));
+        gridTypeBuilder.setX((dimensions.getWidth() + GRID_TILE_SIZE - 1) / GRID_TILE_SIZE);
+        gridTypeBuilder.setY((dimensions.getHeight() + GRID_TILE_SIZE - 1) / GRID_TILE_SIZE);
+        mLogLumaGridAllocation = Allocation.createTyped(rs, gridTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
         HandlerThread processingThread = new HandlerThread("ViewfinderProcessor");
         processingThread.start();
         mProcessingHandler = new Handler(processingThread.getLooper());
 
+        // P010 images can't be received into an allocation, so their luma plane and their
+        // interleaved chroma plane are copied into allocations of 16-bit samples instead
+        if (p010HdrInput) {
+            mInputHdrReader = ImageReader.newInstance(dimensions.getWidth(),
+                    dimensions.getHeight(), ImageFormat.YCBCR_P010, P010_MAX_IMAGES);
+
+            Type.Builder lumaP010TypeBuilder = new Type.Builder(rs, Element.U16(rs));
+            lumaP010TypeBuilder.setX(dimensions.getWidth());
+            lumaP010TypeBuilder.setY(dimensions.getHeight());
+            mInputLumaP010Allocation = Allocation.createTyped(rs, lumaP010TypeBuilder.create(),
+                    Allocation.USAGE_SCRIPT);
+            mInputLumaP010 = new short[dimensions.getWidth() * dimensions.getHeight()];
+
+            Type.Builder chromaP010TypeBuilder = new Type.Builder(rs, Element.U16_2(rs));
+            chromaP010TypeBuilder.setX(dimensions.getWidth() / 2);
+            chromaP010TypeBuilder.setY(dimensions.getHeight() / 2);
+            mInputChromaP010Allocation = Allocation.createTyped(rs,
+                    chromaP010TypeBuilder.create(), Allocation.USAGE_SCRIPT);
+            mInputChromaP010 = new short[(dimensions.getWidth() / 2) *
+                    (dimensions.getHeight() / 2) * 2];
+        }
+
+        Type.Builder maskTypeBuilder = new Type.Builder(rs, Element.U8(rs));
+        maskTypeBuilder.setX(dimensions.getWidth());
+        maskTypeBuilder.setY(dimensions.getHeight());
+        mMotionMaskAllocation = Allocation.createTyped(rs, maskTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
+        // Denoised chroma of the current frame, at the chroma resolution of YUV_420_888
+        Type.Builder chromaTypeBuilder = new Type.Builder(rs, Element.F32_2(rs));
+        chromaTypeBuilder.setX(dimensions.getWidth() / 2);
+        chromaTypeBuilder.setY(dimensions.getHeight() / 2);
+        mDenoisedChromaAllocation = Allocation.createTyped(rs, chromaTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
+        Type.Builder alignCostTypeBuilder = new Type.Builder(rs, Element.F32(rs));
+        alignCostTypeBuilder.setX(ALIGN_CANDIDATES);
+        alignCostTypeBuilder.setY(ALIGN_CANDIDATES);
+        mAlignCostAllocation = Allocation.createTyped(rs, alignCostTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+        alignCostTypeBuilder.setX(ALIGN_REFINE_CANDIDATES);
+        alignCostTypeBuilder.setY(ALIGN_REFINE_CANDIDATES);
+        mAlignRefineCostAllocation = Allocation.createTyped(rs, alignCostTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+
+        mHistogramCountAllocation = Allocation.createSized(rs, Element.U32(rs),
+                HISTOGRAM_COUNT * HISTOGRAM_BINS);
+
         mHdrMergeScript = new ScriptC_hdr_merge(rs);
 
-        mHdrMergeScript.set_gPrevFrame(mPrevAllocation);
+        mHdrMergeScript.set_gHistory(mHistoryAllocation);
+        mHdrMergeScript.bind_gHistogramCounts(mHistogramCountAllocation);
+        mHdrMergeScript.set_gRadiance(mRadianceAllocation);
+        mHdrMergeScript.set_gLogLumaGrid(mLogLumaGridAllocation);
+        mHdrMergeScript.set_gMotionMask(mMotionMaskAllocation);
+        mHdrMergeScript.set_gDenoisedChroma(mDenoisedChromaAllocation);
+        mHdrMergeScript.set_gMerged(mMergedAllocation);
+        mHdrMergeScript.set_gMergedLuma(mMergedLumaAllocation);
 
-        mHdrTask = new ProcessingTask(mInputHdrAllocation, dimensions.getWidth()/2, true);
-        mNormalTask = new ProcessingTask(mInputNormalAllocation, 0, false);
+        setFalseColorPalette(createIrePalette());
+
+        if (p010HdrInput) {
+            mHdrTask = new ProcessingTask(mInputHdrReader, true, true);
+        } else {
+            mHdrTask = new ProcessingTask(mInputHdrAllocation, true, true);
+        }
+        mNormalTask = new ProcessingTask(mInputNormalAllocation, false, false);
 
         setRenderMode(MODE_NORMAL);
     }
 
     public Surface getInputHdrSurface() {
+        if (mInputHdrReader != null) {
+            return mInputHdrReader.getSurface();
+        }
         return mInputHdrAllocation.getSurface();
     }
 
@@ -92,25 +515,513 @@ public class ViewfinderProcessor {
     }
 
     public void setRenderMode(int mode) {
+        if (mode != mMode) {
+            mHistoryStale = true;
+        }
         mMode = mode;
     }
 
+    /**
+     * Select the shape of the split between the two exposures in split-screen mode
+     */
+    public void setSplitShape(int shape) {
+        mSplitShape = shape;
+    }
+
+    /**
+     * Move the split line, or the center of the loupe, to a point given as a fraction of the
+     * frame width and height
+     */
+    public void setSplitPosition(float x, float y) {
+        mSplitX = x;
+        mSplitY = y;
+    }
+
+    /**
+     * Set the angle of the angled split line, clockwise from vertical in degrees
+     */
+    public void setSplitAngle(float degrees) {
+        mSplitAngle = degrees;
+    }
+
+    /**
+     * Set the radius of the loupe as a fraction of the frame height
+     */
+    public void setLoupeRadius(float radius) {
+        mLoupeRadius = radius;
+    }
+
+    /**
+     * Show both sides of the split screen at the same brightness, scaling each frame by the
+     * ratio of the bracket's reference exposure to its own
+     */
+    public void setSplitNormalization(boolean normalize) {
+        mSplitNormalize = normalize;
+    }
+
+    /**
+     * Show zebra stripes over clipped highlights and tint crushed shadows
+     */
+    public void setExposureWarnings(boolean enable) {
+        mZebra = enable;
+    }
+
+    /**
+     * Set the luma, from 0 to 255, at or above which highlights count as clipped and at or
+     * below which shadows count as crushed
+     */
+    public void setExposureWarningThresholds(float high, float low) {
+        mZebraHighThreshold = high;
+        mZebraLowThreshold = low;
+    }
+
+    /**
+     * Draw the in-focus edges of the scene over the viewfinder
+     */
+    public void setFocusPeaking(boolean enable) {
+        mFocusPeaking = enable;
+    }
+
+    /**
+     * Set the luma gradient, in luma steps across an edge, from which edges count as in focus.
+     * Lower values mark softer edges.
+     */
+    public void setFocusPeakingThreshold(float threshold) {
+        mFocusPeakingThreshold = threshold;
+    }
+
+    /**
+     * Set the color focus peaking draws edges in
+     *
+     * @param color an android.graphics.Color int; alpha is ignored
+     */
+    public void setFocusPeakingColor(int color) {
+        mFocusPeakingColor = new Float3((color >> 16) & 0xff, (color >> 8) & 0xff, color & 0xff);
+    }
+
+    /**
+     * Show the viewfinder as a false color map of its luma instead of its colors
+     */
+    public void setFalseColor(boolean enable) {
+        mFalseColor = enable;
+    }
+
+    /**
+     * Set the palette of the false color map. The colors are spread evenly from black to white
+     * luma, so a palette of 256 colors has one for each 8-bit luma value.
+     *
+     * @param colors android.graphics.Color ints, from black up; alpha is ignored
+     */
+    public void setFalseColorPalette(int[] colors) {
+        if (colors.length < 2) {
+            throw new IllegalArgumentException("False color palette needs at least 2 colors");
+        }
+        byte[] rgba = new byte[colors.length * 4];
+        for (int i = 0; i < colors.length; i++) {
+            rgba[4 * i] = (byte) (colors[i] >> 16);
+            rgba[4 * i + 1] = (byte) (colors[i] >> 8);
+            rgba[4 * i + 2] = (byte) colors[i];
+            rgba[4 * i + 3] = (byte) 0xff;
+        }
+        Allocation lut = Allocation.createSized(mRS, Element.U8_4(mRS), colors.length);
+        lut.copyFrom(rgba);
+        mFalseColorLutAllocation = lut;
+    }
+
+    /**
+     * False color palette in the style of cinema monitors, by IRE level: grey, except for
+     * purple and blue crushed blacks, green around mid-grey, pink around skin tones, and
+     * yellow and red near-clipped and clipped highlights
+     */
+    public static int[] createIrePalette() {
+        int[] colors = new int[FALSE_COLOR_LEVELS];
+        for (int i = 0; i < colors.length; i++) {
+            float ire = 100.f * i / (colors.length - 1);
+            if (ire < 2.5f) {
+                colors[i] = 0xff800080;
+            } else if (ire < 4.f) {
+                colors[i] = 0xff0000ff;
+            } else if (ire >= 38.f && ire < 42.f) {
+                colors[i] = 0xff00c000;
+            } else if (ire >= 52.f && ire < 56.f) {
+                colors[i] = 0xffff80c0;
+            } else if (ire >= 97.f && ire < 99.f) {
+                colors[i] = 0xffffff00;
+            } else if (ire >= 99.f) {
+                colors[i] = 0xffff0000;
+            } else {
+                colors[i] = 0xff000000 | i * 0x010101;
+            }
+        }
+        return colors;
+    }
+
+    /**
+     * Color grade the viewfinder with a 3D lookup table, applied to the RGB output
+     *
+     * @param lut the table, or null to turn color grading off
+     */
+    public void setColorLut(CubeLut lut) {
+        if (lut == null) {
+            mColorLutAllocation = null;
+            return;
+        }
+        Type.Builder lutTypeBuilder = new Type.Builder(mRS, Element.F32_4(mRS));
+        lutTypeBuilder.setX(lut.size);
+        lutTypeBuilder.setY(lut.size);
+        lutTypeBuilder.setZ(lut.size);
+        Allocation allocation = Allocation.createTyped(mRS, lutTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+        allocation.copyFrom(lut.table);
+
+        mColorLutDomainMin = new Float3(lut.domainMin[0], lut.domainMin[1], lut.domainMin[2]);
+        mColorLutDomainMax = new Float3(lut.domainMax[0], lut.domainMax[1], lut.domainMax[2]);
+        mColorLutAllocation = allocation;
+    }
+
+    /**
+     * Select how colors between the points of the color grading table are interpolated
+     */
+    public void setColorLutInterpolation(int interpolation) {
+        mColorLutInterpolation = interpolation;
+    }
+
+    /**
+     * Dither the viewfinder output with a threshold texture when quantizing it to 8 bits, to
+     * break up the banding of smooth gradients
+     *
+     * @param thresholds values in [0, 1) row by row, such as a {@link DitherPattern}, or null
+     *                   to round to the nearest level instead
+     * @param size width and height of the texture, which is tiled over the output
+     */
+    public void setDitherPattern(float[] thresholds, int size) {
+        if (thresholds == null) {
+            mDitherTextureAllocation = null;
+            return;
+        }
+        Type.Builder ditherTypeBuilder = new Type.Builder(mRS, Element.F32(mRS));
+        ditherTypeBuilder.setX(size);
+        ditherTypeBuilder.setY(size);
+        Allocation allocation = Allocation.createTyped(mRS, ditherTypeBuilder.create(),
+                Allocation.USAGE_SCRIPT);
+        allocation.copyFrom(thresholds);
+        mDitherTextureAllocation = allocation;
+    }
+
+    /**
+     * Compute the histograms of every processed frame and pass them to a listener
+     *
+     * @param listener the listener, or null to stop computing histograms
+     * @param handler the handler the listener is invoked on
+     */
+    public void setHistogramListener(HistogramListener listener, Handler handler) {
+        synchronized (this) {
+            mHistogramListener = listener;
+            mHistogramHandler = handler;
+        }
+    }
+
+    /**
+     * Select the algorithm used to fuse the even and odd frames in HDR mode
+     */
+    public void setMergeMode(int mergeMode) {
+        mMergeMode = mergeMode;
+    }
+
+    /**
+     * Select the tone mapping operator applied to the radiance and bracket merges
+     */
+    public void setToneMapOperator(int operator) {
+        mToneMapOperator = operator;
+    }
+
+    /**
+     * Set the display value that mid-grey is mapped to by tone mapping
+     */
+    public void setToneMapKey(float key) {
+        mToneMapKey = key;
+    }
+
+    /**
+     * Set the smallest radiance, relative to mid-grey at the key value, shown as full white
+     */
+    public void setToneMapWhitePoint(float white) {
+        mToneMapWhite = white;
+    }
+
+    /**
+     * Set how strongly local tone mapping flattens large-scale brightness differences,
+     * from 0 (not at all) to 1 (completely)
+     */
+    public void setLocalCompression(float compression) {
+        mLocalCompression = compression;
+    }
+
+    /**
+     * Enable or disable falling back to a single frame where motion between the frames
+     * is detected in HDR mode
+     */
+    public void setDeghosting(boolean deghost) {
+        mDeghost = deghost;
+    }
+
+    /**
+     * Enable or disable rendering the deghosting motion mask in false color
+     */
+    public void setDeghostDebug(boolean debug) {
+        mDeghostDebug = debug;
+    }
+
+    /**
+     * Set the radiance difference, as a natural log ratio, above which a pixel counts as moving
+     */
+    public void setDeghostThreshold(float threshold) {
+        mDeghostThreshold = threshold;
+    }
+
+    /**
+     * Enable or disable smoothing the chroma of each frame between pixels of similar luma
+     */
+    public void setChromaDenoise(boolean enable) {
+        mChromaDenoise = enable;
+    }
+
+    /**
+     * Set the distance in chroma samples, two pixels each, over which chroma is smoothed
+     */
+    public void setChromaDenoiseRadius(int radius) {
+        mChromaDenoiseRadius = radius;
+    }
+
+    /**
+     * Set how much of the smoothed chroma replaces the original, from 0 to 1
+     */
+    public void setChromaDenoiseStrength(float strength) {
+        mChromaDenoiseStrength = strength;
+    }
+
+    /**
+     * Enable or disable averaging each frame with the previous frames of the same exposure where
+     * they differ by no more than noise
+     */
+    public void setTemporalDenoise(boolean enable) {
+        mTemporalDenoise = enable;
+    }
+
+    /**
+     * Set the weight of the previous frames in the temporal average of a static pixel, from 0
+     * (no denoising) to below 1 (the longer the memory, the more noise is removed)
+     */
+    public void setTemporalDenoiseStrength(float strength) {
+        mTemporalDenoiseStrength = strength;
+    }
+
+    /**
+     * Set the standard deviation of the noise at mid-grey and ISO 100, in 8-bit luma steps,
+     * below which differences between frames are averaged out. It is scaled up for frames
+     * captured at higher sensitivities.
+     */
+    public void setTemporalDenoiseNoise(float noise) {
+        mTemporalDenoiseNoise = noise;
+    }
+
+    /**
+     * Enable or disable sharpening the luma of the output with an unsharp mask
+     */
+    public void setSharpening(boolean enable) {
+        mSharpen = enable;
+    }
+
+    /**
+     * Set the standard deviation in pixels of the blur that detail is measured against
+     */
+    public void setSharpenRadius(float radius) {
+        mSharpenRadius = radius;
+    }
+
+    /**
+     * Set how much detail is added back, 0 for none
+     */
+    public void setSharpenAmount(float amount) {
+        mSharpenAmount = amount;
+    }
+
+    /**
+     * Set the detail, in 8-bit luma steps, that is too small to be sharpened
+     */
+    public void setSharpenThreshold(float threshold) {
+        mSharpenThreshold = threshold;
+    }
+
+    /**
+     * Enable or disable compensating for camera shake between the frames before merging them
+     */
+    public void setAlignment(boolean align) {
+        mAlign = align;
+    }
+
+    /**
+     * Override the dataspace the camera output is converted from YUV to RGB with, for streams
+     * that report the wrong one. Only its color standard and range are used; the transfer
+     * function always comes from the stream.
+     *
+     * @param dataSpace an android.hardware.DataSpace value, such as {@link #DATASPACE_JFIF}, or
+     *     {@link #DATASPACE_UNKNOWN} to use the dataspace of the stream
+     */
+    public void setInputDataSpace(int dataSpace) {
+        mInputDataSpace = dataSpace;
+    }
+
+    /**
+     * Select the YUV to RGB conversion and the transfer function matching a dataspace. Unknown
+     * color standards fall back to BT.601, unknown ranges to full range, and transfer functions
+     * other than HLG and PQ to a gamma curve.
+     */
+    private void setColorConversion(int dataSpace) {
+        // Luma weights of red and blue
+        float kr;
+        float kb;
+        switch (dataSpace & DATASPACE_STANDARD_MASK) {
+            case DATASPACE_STANDARD_BT709:
+                kr = 0.2126f;
+                kb = 0.0722f;
+                break;
+            case DATASPACE_STANDARD_BT2020:
+                kr = 0.2627f;
+                kb = 0.0593f;
+                break;
+            case DATASPACE_STANDARD_BT601_625:
+            case DATASPACE_STANDARD_BT601_525:
+            default:
+                kr = 0.299f;
+                kb = 0.114f;
+                break;
+        }
+        float kg = 1.f - kr - kb;
+
+        mHdrMergeScript.set_gColorMatrixRV(2.f * (1.f - kr));
+        mHdrMergeScript.set_gColorMatrixGU(-2.f * kb * (1.f - kb) / kg);
+        mHdrMergeScript.set_gColorMatrixGV(-2.f * kr * (1.f - kr) / kg);
+        mHdrMergeScript.set_gColorMatrixBU(2.f * (1.f - kb));
+        mHdrMergeScript.set_gLimitedRange(
+                (dataSpace & DATASPACE_RANGE_MASK) == DATASPACE_RANGE_LIMITED ? 1 : 0);
+
+        int transferFunction;
+        switch (dataSpace & DATASPACE_TRANSFER_MASK) {
+            case DATASPACE_TRANSFER_HLG:
+                transferFunction = TRANSFER_FUNCTION_HLG;
+                break;
+            case DATASPACE_TRANSFER_ST2084:
+                transferFunction = TRANSFER_FUNCTION_PQ;
+                break;
+            default:
+                transferFunction = TRANSFER_FUNCTION_GAMMA;
+                break;
+        }
+        mHdrMergeScript.set_gTransferFunction(transferFunction);
+    }
+
+    /**
+     * Set the exposure parameters of the HDR burst, for merge modes that take them into account.
+     * The burst is assumed to repeat the exposures in order, one per frame.
+     *
+     * @param exposures exposure time of each frame of the burst, in nanoseconds; at most
+     *                  {@link #MAX_BRACKET_LENGTH} of them
+     * @param sensitivity ISO sensitivity of all frames
+     */
+    public void setHdrExposures(long[] exposures, int sensitivity) {
+        if (exposures.length < 2 || exposures.length > MAX_BRACKET_LENGTH) {
+            throw new IllegalArgumentException("Unsupported bracket length " + exposures.length);
+        }
+        float[] bracketExposures = new float[exposures.length];
+        for (int i = 0; i < exposures.length; i++) {
+            bracketExposures[i] = exposures[i] / 1e6f;
+        }
+        float gain = sensitivity / 100.f;
+        if (!Arrays.equals(bracketExposures, mBracketExposures) || gain != mGain) {
+            mHistoryStale = true;
+        }
+        mBracketExposures = bracketExposures;
+        mGain = gain;
+    }
+
+    /**
+     * Record which exposure of the bracket set by {@link #setHdrExposures} the frame captured
+     * at a sensor timestamp is, so that dropped frames don't mix up the exposures.
+     *
+     * @param timestamp the start of exposure timestamp reported for the capture
+     * @param bracketIndex the index of the exposure in the bracket
+     */
+    public void setFrameBracketIndex(long timestamp, int bracketIndex) {
+        synchronized (mTaggedTimestamps) {
+            mTaggedTimestamps[mNextTaggedFrame] = timestamp;
+            mTaggedBracketIndices[mNextTaggedFrame] = bracketIndex;
+            mTaggedExposures[mNextTaggedFrame] = 0;
+            mTaggedSensitivities[mNextTaggedFrame] = 0;
+            mNextTaggedFrame = (mNextTaggedFrame + 1) % TAGGED_FRAMES;
+        }
+    }
+
+    /**
+     * Record the exposure a frame tagged with {@link #setFrameBracketIndex} was actually captured
+     * with, so that merging and the histograms go by it rather than by the exposure requested for
+     * its place in the bracket, which may since have changed.
+     *
+     * @param timestamp the start of exposure timestamp reported for the capture
+     * @param exposure the exposure time of the capture result, in nanoseconds
+     * @param sensitivity the ISO sensitivity of the capture result
+     */
+    public void setFrameExposure(long timestamp, long exposure, int sensitivity) {
+        synchronized (mTaggedTimestamps) {
+            int tagged = findTaggedFrame(timestamp);
+            if (tagged >= 0) {
+                mTaggedExposures[tagged] = exposure;
+                mTaggedSensitivities[tagged] = sensitivity;
+            }
+        }
+    }
+
+    /**
+     * Index in the tagged frames of the frame captured at a sensor timestamp, or -1 if it isn't
+     * known. Must be called with mTaggedTimestamps locked.
+     */
+    private int findTaggedFrame(long timestamp) {
+        for (int i = 0; i < TAGGED_FRAMES; i++) {
+            if (mTaggedBracketIndices[i] >= 0 && mTaggedTimestamps[i] == timestamp) {
+                return i;
+            }
+        }
+        return -1;
+    }
+
     /**
      * Simple class to keep track of incoming frame count,
      * and to process the newest one in the processing thread
      */
-    class ProcessingTask implements Runnable, Allocation.OnBufferAvailableListener {
+    class ProcessingTask implements Runnable, Allocation.OnBufferAvailableListener,
+            ImageReader.OnImageAvailableListener {
         private int mPendingFrames = 0;
-        private int mFrameCounter = 0;
-        private int mCutPointX;
+        private int mLastBracketIndex = -1;
+        private boolean mSplitScreen;
         private boolean mCheckMerge;
 
         private Allocation mInputAllocation;
+        private ImageReader mInputReader;
 
-        public ProcessingTask(Allocation input, int cutPointX, boolean checkMerge) {
+        public ProcessingTask(Allocation input, boolean splitScreen, boolean checkMerge) {
             mInputAllocation = input;
             mInputAllocation.setOnBufferAvailableListener(this);
-            mCutPointX = cutPointX;
+            mSplitScreen = splitScreen;
+            mCheckMerge = checkMerge;
+        }
+
+        /**
+         * Process the P010 images of a reader instead of the buffers of an allocation
+         */
+        public ProcessingTask(ImageReader input, boolean splitScreen, boolean checkMerge) {
+            mInputReader = input;
+            mInputReader.setOnImageAvailableListener(this, mProcessingHandler);
+            mSplitScreen = splitScreen;
             mCheckMerge = checkMerge;
         }
 
@@ -122,6 +1033,14 @@ public class ViewfinderProcessor {
             }
         }
 
+        @Override
+        public void onImageAvailable(ImageReader reader) {
+            synchronized(this) {
+                mPendingFrames++;
+                mProcessingHandler.post(this);
+            }
+        }
+
         @Override
         public void run() {
 
@@ -136,23 +1055,362 @@ public class ViewfinderProcessor {
             }
 
             // Get to newest input
-            for (int i = 0; i < pendingFrames; i++) {
-                mInputAllocation.ioReceive();
+            long timestamp = -1;
+            // The camera configures YUV_420_888 streams with the JFIF dataspace, which an
+            // allocation has no way to report; images carry theirs
+            int streamDataSpace = DATASPACE_JFIF;
+            if (mInputReader != null) {
+                Image image = mInputReader.acquireLatestImage();
+                if (image == null) return;
+                copyP010Planes(image);
+                timestamp = image.getTimestamp();
+                if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.TIRAMISU) {
+                    streamDataSpace = image.getDataSpace();
+                }
+                image.close();
+            } else {
+                for (int i = 0; i < pendingFrames; i++) {
+                    mInputAllocation.ioReceive();
+                }
+                if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.M) {
+                    timestamp = mInputAllocation.getTimeStamp();
+                }
+            }
+
+            // Identify the exposure of the frame by its timestamp. Until the camera has reported
+            // it, assume the burst went on through the bracket without dropping frames, and
+            // until its capture result has arrived, that it got the exposure requested for it.
+            float[] bracketExposures = mCheckMerge ? mBracketExposures : AUTO_EXPOSURE_BRACKET;
+            int bracketIndex = -1;
+            long exposureTime = 0;
+            int sensitivity = 0;
+            if (timestamp >= 0) {
+                synchronized (mTaggedTimestamps) {
+                    int tagged = findTaggedFrame(timestamp);
+                    if (tagged >= 0) {
+                        bracketIndex = mTaggedBracketIndices[tagged];
+                        exposureTime = mTaggedExposures[tagged];
+                        sensitivity = mTaggedSensitivities[tagged];
+                    }
+                }
+            }
+            if (bracketIndex < 0) {
+                bracketIndex = mLastBracketIndex + 1;
+            }
+            bracketIndex %= bracketExposures.length;
+            mLastBracketIndex = bracketIndex;
+
+            float exposure = bracketExposures[bracketIndex];
+            float gain = mGain;
+            if (mCheckMerge && exposureTime > 0 && sensitivity > 0) {
+                exposure = exposureTime / 1e6f;
+                gain = sensitivity / 100.f;
+            }
+
+            if (mHistoryStale || mLastTask != this) {
+                mHistoryStale = false;
+                clearHistory();
+            }
+            mLastTask = this;
+
+            int frame = mFrameCounter++;
+            int slot = frame % HISTORY_LENGTH;
+            mSlotBracketIndex[slot] = bracketIndex;
+            mSlotExposure[slot] = exposure;
+            mSlotGain[slot] = gain;
+            mHdrMergeScript.set_gHistorySlot(slot);
+            mHdrMergeScript.set_gBracketLength(bracketExposures.length);
+            mHdrMergeScript.set_gSlotBracketIndex(mSlotBracketIndex);
+            mHdrMergeScript.set_gSlotExposure(mSlotExposure);
+            mHdrMergeScript.set_gSlotGain(mSlotGain);
+            mHdrMergeScript.set_gReferenceScale(referenceScale(bracketExposures));
+
+            mHdrMergeScript.set_gFrameCounter(frame);
+            mHdrMergeScript.set_gInputP010(mInputReader != null ? 1 : 0);
+            int dataSpace = streamDataSpace != DATASPACE_UNKNOWN ? streamDataSpace :
+                    DATASPACE_JFIF;
+            if (mInputDataSpace != DATASPACE_UNKNOWN) {
+                dataSpace = (mInputDataSpace & ~DATASPACE_TRANSFER_MASK) |
+                        (dataSpace & DATASPACE_TRANSFER_MASK);
+            }
+            setColorConversion(dataSpace);
+            if (mInputReader != null) {
+                mHdrMergeScript.set_gCurrentLumaP010(mInputLumaP010Allocation);
+                mHdrMergeScript.set_gCurrentChromaP010(mInputChromaP010Allocation);
+            } else {
+                mHdrMergeScript.set_gCurrentFrame(mInputAllocation);
+            }
+            mHdrMergeScript.set_gChromaDenoise(mChromaDenoise ? 1 : 0);
+            mHdrMergeScript.set_gChromaDenoiseRadius(mChromaDenoiseRadius);
+            mHdrMergeScript.set_gChromaDenoiseStrength(mChromaDenoiseStrength);
+            mHdrMergeScript.set_gTemporalDenoise(mTemporalDenoise ? 1 : 0);
+            mHdrMergeScript.set_gTemporalDenoiseStrength(mTemporalDenoiseStrength);
+            mHdrMergeScript.set_gTemporalDenoiseNoise(mTemporalDenoiseNoise);
+            mHdrMergeScript.set_gSharpen(mSharpen ? 1 : 0);
+            mHdrMergeScript.set_gSharpenRadius(mSharpenRadius);
+            mHdrMergeScript.set_gSharpenAmount(mSharpenAmount);
+            mHdrMergeScript.set_gSharpenThreshold(mSharpenThreshold);
+            mHdrMergeScript.set_gZebra(mZebra ? 1 : 0);
+            mHdrMergeScript.set_gZebraHighThreshold(mZebraHighThreshold);
+            mHdrMergeScript.set_gZebraLowThreshold(mZebraLowThreshold);
+            mHdrMergeScript.set_gFocusPeaking(mFocusPeaking ? 1 : 0);
+            mHdrMergeScript.set_gFocusPeakingThreshold(mFocusPeakingThreshold);
+            mHdrMergeScript.set_gFocusPeakingColor(mFocusPeakingColor);
+            mHdrMergeScript.set_gFalseColor(mFalseColor ? 1 : 0);
+            mHdrMergeScript.set_gFalseColorLut(mFalseColorLutAllocation);
+            Allocation colorLut = mColorLutAllocation;
+            mHdrMergeScript.set_gColorGrading(colorLut != null ? 1 : 0);
+            if (colorLut != null) {
+                mHdrMergeScript.set_gColorLut(colorLut);
+                mHdrMergeScript.set_gColorLutDomainMin(mColorLutDomainMin);
+                mHdrMergeScript.set_gColorLutDomainMax(mColorLutDomainMax);
+                mHdrMergeScript.set_gColorLutInterpolation(mColorLutInterpolation);
+            }
+            Allocation ditherTexture = mDitherTextureAllocation;
+            mHdrMergeScript.set_gDither(ditherTexture != null ? 1 : 0);
+            if (ditherTexture != null) {
+                mHdrMergeScript.set_gDitherTexture(ditherTexture);
+            }
+            mHdrMergeScript.set_gSplitScreen(mSplitScreen ? 1 : 0);
+            if (mSplitScreen) {
+                setSplitGeometry();
+            }
+            boolean doMerge = mCheckMerge && mMode == MODE_HDR;
+            if (doMerge) {
+                mHdrMergeScript.set_gMergeMode(mMergeMode);
+                mHdrMergeScript.set_gToneMapOperator(mToneMapOperator);
+                mHdrMergeScript.set_gToneMapKey(mToneMapKey);
+                mHdrMergeScript.set_gToneMapWhite(mToneMapWhite);
+                mHdrMergeScript.set_gLocalCompression(mLocalCompression);
+                mHdrMergeScript.set_gDeghost(mDeghost ? 1 : 0);
+                mHdrMergeScript.set_gDeghostDebug(mDeghostDebug ? 1 : 0);
+                mHdrMergeScript.set_gDeghostThreshold(mDeghostThreshold);
+            } else {
+                mHdrMergeScript.set_gMergeMode(MERGE_NONE);
             }
 
-            mHdrMergeScript.set_gFrameCounter(mFrameCounter++);
-            mHdrMergeScript.set_gCurrentFrame(mInputAllocation);
-            mHdrMergeScript.set_gCutPointX(mCutPointX);
-            if (mCheckMerge && mMode == MODE_HDR) {
-                mHdrMergeScript.set_gDoMerge(1);
+            if (doMerge && mAlign) {
+                updateSlotOffsets(slot);
             } else {
-                mHdrMergeScript.set_gDoMerge(0);
+                Arrays.fill(mSlotOffsetX, 0);
+                Arrays.fill(mSlotOffsetY, 0);
+            }
+            mHdrMergeScript.set_gSlotOffsetX(mSlotOffsetX);
+            mHdrMergeScript.set_gSlotOffsetY(mSlotOffsetY);
+
+            // Like the motion mask, the denoised chroma is read from neighboring pixels by the
+            // merge, so it has to be complete first
+            if (mChromaDenoise) {
+                mHdrMergeScript.forEach_denoiseChroma(mDenoisedChromaAllocation);
+            }
+
+            // The motion mask has to be complete before the merge reads its neighborhoods
+            if (doMerge && mDeghost) {
+                mHdrMergeScript.forEach_detectMotion(mMotionMaskAllocation);
             }
 
             // Run processing pass
-            mHdrMergeScript.forEach_mergeHdrFrames(mPrevAllocation, mOutputAllocation);
+            mHdrMergeScript.forEach_mergeHdrFrames(mOutputAllocation);
+
+            // Local tone mapping needs the whole radiance merge first, so it runs as two more
+            // passes that replace the globally tone-mapped output
+            boolean radianceMerge = mMergeMode == MERGE_RADIANCE || mMergeMode == MERGE_BRACKET;
+            if (doMerge && radianceMerge && mToneMapOperator == TONEMAP_LOCAL) {
+                mHdrMergeScript.forEach_buildLuminanceGrid(mLogLumaGridAllocation);
+                mHdrMergeScript.forEach_localToneMap(mRadianceAllocation, mOutputAllocation);
+            }
+
+            // Sharpening reads the neighborhood of each pixel of the finished merge, so it too
+            // runs as a pass of its own
+            if (mSharpen) {
+                mHdrMergeScript.forEach_sharpenLuma(mMergedAllocation, mOutputAllocation);
+            }
+
+            if (frame % HISTOGRAM_FRAME_INTERVAL == 0) {
+                readHistograms(bracketIndex, exposure * gain / mGain);
+            }
             mOutputAllocation.ioSend();
         }
+
+        /**
+         * Compute the histograms of the current frame and its output, and post them to the
+         * histogram listener if there is one and it's done with the last ones
+         */
+        private void readHistograms(int bracketIndex, float exposure) {
+            HistogramListener listener;
+            Handler handler;
+            synchronized (ViewfinderProcessor.this) {
+                listener = mHistogramListener;
+                handler = mHistogramHandler;
+            }
+            if (listener == null || mHistogramsPosted) return;
+
+            mHistogramCountAllocation.copyFrom(mHistogramZeros);
+            mHdrMergeScript.forEach_countHistograms(mOutputAllocation);
+            mHistogramCountAllocation.copyTo(mHistogramCounts);
+            mHistograms.setCounts(mHistogramCounts);
+            mHistograms.bracketIndex = mCheckMerge ? bracketIndex : -1;
+            mHistograms.exposure = mCheckMerge ? Math.round(exposure * 1e6) : 0;
+            mPostedHistogramListener = listener;
+            mHistogramsPosted = true;
+            handler.post(mPostHistograms);
+        }
+
+        /**
+         * Copy the luma and chroma planes of a P010 image into the allocations the kernels read
+         * it from
+         */
+        private void copyP010Planes(Image image) {
+            int width = mDimensions.getWidth();
+            int height = mDimensions.getHeight();
+            Image.Plane[] planes = image.getPlanes();
+            copyP010Plane(planes[0], width, height, mInputLumaP010, 0, 1);
+            // The U and V planes are views of the same interleaved buffer, each starting at its
+            // own first sample
+            copyP010Plane(planes[1], width / 2, height / 2, mInputChromaP010, 0, 2);
+            copyP010Plane(planes[2], width / 2, height / 2, mInputChromaP010, 1, 2);
+            mInputLumaP010Allocation.copyFrom(mInputLumaP010);
+            mInputChromaP010Allocation.copyFrom(mInputChromaP010);
+        }
+
+        /**
+         * Mark every history slot empty, so that no frame taken before is merged
+         */
+        private void clearHistory() {
+            Arrays.fill(mSlotBracketIndex, -1);
+            Arrays.fill(mSlotExposure, 0.f);
+            Arrays.fill(mSlotGain, 0.f);
+            Arrays.fill(mSlotOffsetX, 0);
+            Arrays.fill(mSlotOffsetY, 0);
+        }
+
+        private void setSplitGeometry() {
+            int width = mDimensions.getWidth();
+            int height = mDimensions.getHeight();
+            double angle = Math.toRadians(mSplitAngle);
+
+            mHdrMergeScript.set_gSplitShape(mSplitShape);
+            mHdrMergeScript.set_gSplitX(Math.round(mSplitX * width));
+            mHdrMergeScript.set_gSplitY(Math.round(mSplitY * height));
+            // Rotating the line clockwise on screen, where y points down, rotates its normal
+            // from the x axis towards the y axis
+            mHdrMergeScript.set_gSplitNormal(
+                    new Float2((float) Math.cos(angle), (float) Math.sin(angle)));
+            mHdrMergeScript.set_gLoupeRadius(mLoupeRadius * height);
+            mHdrMergeScript.set_gSplitNormalize(mSplitNormalize ? 1 : 0);
+        }
+
+        /**
+         * Radiance scale of the geometric mean exposure of the bracket
+         */
+        private float referenceScale(float[] bracketExposures) {
+            double logSum = 0;
+            for (float exposure : bracketExposures) {
+                logSum += Math.log(exposure);
+            }
+            return (float) Math.exp(logSum / bracketExposures.length) * mGain;
+        }
+
+        /**
+         * Find the translation of the previous frame that best matches the current frame, with
+         * a coarse search around no motion refined by a one pixel search around its best
+         * candidate. Where nothing can be compared, the previous frame is taken not to have
+         * moved. Earlier frames were aligned to the previous frame, so their offsets relative to
+         * the current frame accumulate it.
+         */
+        private void updateSlotOffsets(int currentSlot) {
+            int[] prevOffset = {0, 0};
+            if (searchAlignment(ALIGN_SEARCH_RADIUS, ALIGN_SEARCH_STEP, mAlignCostAllocation,
+                    mAlignCosts, prevOffset)) {
+                searchAlignment(ALIGN_REFINE_RADIUS, 1, mAlignRefineCostAllocation,
+                        mAlignRefineCosts, prevOffset);
+            }
+            for (int i = 0; i < HISTORY_LENGTH; i++) {
+                if (i == currentSlot) {
+                    mSlotOffsetX[i] = 0;
+                    mSlotOffsetY[i] = 0;
+                } else {
+                    mSlotOffsetX[i] += prevOffset[0];
+                    mSlotOffsetY[i] += prevOffset[1];
+                }
+            }
+        }
+
+        /**
+         * Run the alignment cost pass over the candidates radius steps around an offset, and
+         * move the offset to the best of them. Of equally good candidates the one closest to
+         * the center wins, so that a featureless frame isn't taken to have moved.
+         *
+         * @return false, leaving the offset alone, if no candidate could be compared
+         */
+        private boolean searchAlignment(int radius, int step, Allocation costAllocation,
+                float[] costs, int[] offset) {
+            mHdrMergeScript.set_gAlignCenterX(offset[0]);
+            mHdrMergeScript.set_gAlignCenterY(offset[1]);
+            mHdrMergeScript.set_gAlignRadius(radius);
+            mHdrMergeScript.set_gAlignStep(step);
+            mHdrMergeScript.forEach_alignmentCost(costAllocation);
+            costAllocation.copyTo(costs);
+
+            int size = 2 * radius + 1;
+            int bestX = radius;
+            int bestY = radius;
+            for (int y = 0; y < size; y++) {
+                for (int x = 0; x < size; x++) {
+                    float cost = costs[y * size + x];
+                    float bestCost = costs[bestY * size + bestX];
+                    int distance = (x - radius) * (x - radius) + (y - radius) * (y - radius);
+                    int bestDistance = (bestX - radius) * (bestX - radius) +
+                            (bestY - radius) * (bestY - radius);
+                    if (cost < bestCost || (cost == bestCost && distance < bestDistance)) {
+                        bestX = x;
+                        bestY = y;
+                    }
+                }
+            }
+            if (costs[bestY * size + bestX] >= ALIGN_INVALID_COST) return false;
+
+            offset[0] += (bestX - radius) * step;
+            offset[1] += (bestY - radius) * step;
+            return true;
+        }
     }
 
+    /**
+     * Copy the little-endian 16-bit samples of an image plane into every channels'th element of
+     * an array, starting at the given channel. Packed planes go straight into the array, a whole
+     * plane or a row at a time; others are read a row at a time and then spread out.
+     */
+    private void copyP010Plane(Image.Plane plane, int width, int height, short[] out,
+            int channel, int channels) {
+        ShortBuffer samples = plane.getBuffer().order(ByteOrder.LITTLE_ENDIAN).asShortBuffer();
+        int rowStride = plane.getRowStride() / 2;
+        int pixelStride = plane.getPixelStride() / 2;
+        if (pixelStride == 1 && channels == 1) {
+            if (rowStride == width) {
+                samples.get(out, 0, width * height);
+                return;
+            }
+            for (int y = 0; y < height; y++) {
+                samples.position(y * rowStride);
+                samples.get(out, y * width, width);
+            }
+            return;
+        }
+
+        // The last row of a plane may end right after its last sample, without the padding
+        int rowLength = (width - 1) * pixelStride + 1;
+        if (mInputRowP010.length < rowLength) {
+            mInputRowP010 = new short[rowLength];
+        }
+        for (int y = 0; y < height; y++) {
+            samples.position(y * rowStride);
+            samples.get(mInputRowP010, 0, rowLength);
+            int index = y * width * channels + channel;
+            for (int x = 0; x < width; x++) {
+                out[index] = mInputRowP010[x * pixelStride];
+                index += channels;
+            }
+        }
+    }
 }
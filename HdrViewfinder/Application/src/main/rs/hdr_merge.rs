@@ -18,64 +18,1221 @@
 #pragma rs_fp_relaxed
 
 rs_allocation gCurrentFrame;
-rs_allocation gPrevFrame;
+// P010 input: the current frame as its 16-bit luma plane and interleaved U, V chroma plane, read
+// instead of gCurrentFrame
+int gInputP010 = 0;
+rs_allocation gCurrentLumaP010;
+rs_allocation gCurrentChromaP010;
+rs_allocation gHistory;
+rs_allocation gRadiance;
+rs_allocation gLogLumaGrid;
+rs_allocation gMotionMask;
+rs_allocation gDenoisedChroma;
+rs_allocation gMerged;
+rs_allocation gMergedLuma;
 
-int gCutPointX = 0;
-int gDoMerge = 0;
+int gMergeMode = 0;
 int gFrameCounter = 0;
 
-uchar4 __attribute__((kernel)) mergeHdrFrames(uchar4 prevPixel, uint32_t x, uint32_t y) {
-
-    // Read in pixel values from latest frame - YUV color space
-
-    uchar4 curPixel;
-    curPixel.r = rsGetElementAtYuv_uchar_Y(gCurrentFrame, x, y);
-    curPixel.g = rsGetElementAtYuv_uchar_U(gCurrentFrame, x, y);
-    curPixel.b = rsGetElementAtYuv_uchar_V(gCurrentFrame, x, y);
-    curPixel.a = 255;
-
-    uchar4 mergedPixel;
-    if (gDoMerge == 1) {
-        // Complex HDR fusion technique
-        mergedPixel = curPixel / 2 + prevPixel / 2;
-
-        /* Experimental color saturation boosting merge
-        mergedPixel.r = curPixel.r / 2 + prevPixel.r / 2;
-
-        uchar saturationCurrent = abs(curPixel.g - 128) + abs(curPixel.b - 128);
-        uchar saturationPrev = abs(prevPixel.g - 128) + abs(prevPixel.b - 128);
-        mergedPixel.g = saturationCurrent > saturationPrev ? curPixel.g : prevPixel.g;
-        mergedPixel.b = saturationCurrent > saturationPrev ? curPixel.b : prevPixel.b;
-        */
-    } else if (gCutPointX > 0) {
-        // Composite side by side
-        mergedPixel = ((x < gCutPointX) ^ (gFrameCounter & 0x1)) ?
-                curPixel : prevPixel;
+// Number of recent frames kept in the slices of gHistory, must match
+// ViewfinderProcessor.HISTORY_LENGTH
+#define HISTORY_LENGTH 6
+
+// gHistory slice the current frame is stored in; the previous frame is in the slice before it
+int gHistorySlot = 0;
+// Number of frames in the exposure bracket, at most HISTORY_LENGTH - 1 so that the previous
+// frame of each exposure is still in gHistory for temporal denoising
+int gBracketLength = 2;
+
+// Per history slot: exposure time in milliseconds, analog gain, and translation of the frame
+// relative to the current one in pixels
+float gSlotExposure[HISTORY_LENGTH];
+float gSlotGain[HISTORY_LENGTH];
+int gSlotOffsetX[HISTORY_LENGTH];
+int gSlotOffsetY[HISTORY_LENGTH];
+// Per history slot: index in the bracket of the frame's exposure, or -1 if the slot is empty
+int gSlotBracketIndex[HISTORY_LENGTH];
+
+// Candidates of the alignmentCost pass: offsets of the previous frame gAlignRadius steps of
+// gAlignStep pixels around gAlignCenter in each direction
+int gAlignCenterX = 0;
+int gAlignCenterY = 0;
+int gAlignRadius = 8;
+int gAlignStep = 2;
+
+// Split-screen comparison of the latest frames of the two exposures, for MERGE_NONE: whether to show it, its
+// shape (SPLIT_ ints) and the point in pixels the split line goes through or the loupe is
+// centered on. Even frames are shown on the left, top, back side of the line, or in the loupe.
+int gSplitScreen = 0;
+int gSplitShape = 0;
+int gSplitX = 0;
+int gSplitY = 0;
+// Unit normal of the line of SPLIT_ANGLE, pointing towards the side odd frames are shown on
+float2 gSplitNormal = {1.f, 0.f};
+// Radius in pixels of SPLIT_LOUPE
+float gLoupeRadius = 0.f;
+// Scale both sides of the split to the reference exposure, so that they only differ in noise
+// and clipping
+int gSplitNormalize = 0;
+
+// Exposure warnings: zebra stripes over highlights with a luma of at least gZebraHighThreshold,
+// and a tint over shadows with a luma of at most gZebraLowThreshold
+int gZebra = 0;
+float gZebraHighThreshold = 255.f;
+float gZebraLowThreshold = 8.f;
+
+// Focus peaking: edges of the current frame with a luma gradient of at least
+// gFocusPeakingThreshold are drawn over the output in gFocusPeakingColor, an RGB color in the
+// 8-bit range
+int gFocusPeaking = 0;
+float gFocusPeakingThreshold = 48.f;
+float3 gFocusPeakingColor = {255.f, 0.f, 0.f};
+
+// False color exposure map: the output is replaced by the color gFalseColorLut, a 1D uchar4
+// allocation of any size spanning the full luma range, holds for the luma of each pixel
+int gFalseColor = 0;
+rs_allocation gFalseColorLut;
+
+// Color grading of the output with gColorLut, a 3D float4 allocation indexed by red, green and
+// blue. Color values from gColorLutDomainMin to gColorLutDomainMax, in the 0 to 1 range, span
+// the table, and are interpolated between its points with a LUT_ method.
+int gColorGrading = 0;
+rs_allocation gColorLut;
+float3 gColorLutDomainMin = {0.f, 0.f, 0.f};
+float3 gColorLutDomainMax = {1.f, 1.f, 1.f};
+int gColorLutInterpolation = 0;
+
+// Dithering of the final quantization to 8 bits. The output stays in floating point through the
+// color conversion and grading, and is rounded with the threshold gDitherTexture, a 2D float
+// allocation of values in [0, 1) tiled over the output, holds for each pixel. The thresholds
+// shift every frame, so that the pattern averages out over time.
+int gDither = 0;
+rs_allocation gDitherTexture;
+
+// YUV to RGB matrix, as the coefficients of the chroma channels centered on 0:
+// R = Y + RV * V, G = Y + GU * U + GV * V, B = Y + BU * U
+// Defaults to full-range BT.601 (JFIF). With gLimitedRange, the input Y spans [16, 235] and
+// chroma [16, 240]; both are expanded to the full range as the frame is read, so that merging,
+// tone mapping, exposure warnings and the matrix all work on full range values.
+float gColorMatrixRV = 1.402f;
+float gColorMatrixGU = -0.34414f;
+float gColorMatrixGV = -0.71414f;
+float gColorMatrixBU = 1.772f;
+int gLimitedRange = 0;
+// Transfer function of the camera output, one of the TRANSFER_FUNCTION_ defines
+int gTransferFunction = 0;
+
+// Radiance scale of an exposure in the middle of the bracket, which the tone mapping key is
+// relative to
+float gReferenceScale = 1.f;
+
+// Tone mapping of the radiance merge: operator, scene key (the display value mid-grey is mapped
+// to) and white point (the smallest scaled radiance shown as full white)
+int gToneMapOperator = 0;
+float gToneMapKey = 0.18f;
+float gToneMapWhite = 4.f;
+// How much local tone mapping flattens the low-frequency luminance; 0 keeps it, 1 removes it
+float gLocalCompression = 0.5f;
+
+// Temporal denoising: each frame is blended with the denoised previous frame of the same
+// exposure, kept in gHistory, before it is merged and stored. The previous frame gets a weight
+// of up to gTemporalDenoiseStrength, dropping to zero where the luma of the frames, or either of
+// their chroma channels, differs by more than the noise, whose standard deviation at mid-grey
+// is gTemporalDenoiseNoise at an analog gain of 1 (ISO 100). The noise grows with the square
+// root of the gain the frame was captured with.
+int gTemporalDenoise = 0;
+float gTemporalDenoiseStrength = 0.8f;
+float gTemporalDenoiseNoise = 4.f;
+
+// Chroma denoising: before the merge, the chroma of the current frame is smoothed into
+// gDenoisedChroma, at chroma resolution, by a joint bilateral filter guided by luma. Neighbors
+// up to gChromaDenoiseRadius chroma samples away are averaged in only where their luma is close,
+// so that colors don't bleed across edges, and gChromaDenoiseStrength mixes the result with the
+// original chroma. Luma is left untouched.
+int gChromaDenoise = 0;
+int gChromaDenoiseRadius = 2;
+float gChromaDenoiseStrength = 1.f;
+
+// Sharpening: an unsharp mask on the luma of the merged output, run as a separate pass over gMerged
+// that replaces the output. The merge keeps its luma in gMergedLuma as well, which is all the blur
+// and clamp read around each pixel. Detail is the difference from a Gaussian blur with a standard
+// deviation of gSharpenRadius pixels; detail up to gSharpenThreshold luma steps, such as noise, is
+// left alone, and the rest is boosted by gSharpenAmount. The result is clamped to the range of the
+// pixel's neighbors, so edges get steeper without halos.
+int gSharpen = 0;
+float gSharpenRadius = 1.f;
+float gSharpenAmount = 0.5f;
+float gSharpenThreshold = 2.f;
+
+// Deghosting: replace the merge with the best-exposed frame where the two frames disagree by
+// more than the threshold (a natural log radiance ratio), optionally showing the motion mask
+int gDeghost = 0;
+int gDeghostDebug = 0;
+float gDeghostThreshold = 0.4f;
+
+// HDR merge algorithms for gMergeMode, must match ViewfinderProcessor.MERGE_ ints
+#define MERGE_NONE 0
+#define MERGE_AVERAGE 1
+#define MERGE_FUSION 2
+#define MERGE_SATURATION 3
+#define MERGE_RADIANCE 4
+#define MERGE_BRACKET 5
+
+// Zebra stripes run diagonally, ZEBRA_PERIOD pixels apart and half as wide, moving by a pixel
+// every frame
+#define ZEBRA_PERIOD 8
+// Color of crushed shadows, and how strongly it is blended over them
+#define SHADOW_WARNING_COLOR ((float3) {0.f, 64.f, 255.f})
+#define SHADOW_WARNING_OPACITY 0.6f
+
+// Interpolation methods of the color grading table for gColorLutInterpolation, must match
+// ViewfinderProcessor.LUT_ ints
+#define LUT_TRILINEAR 0
+#define LUT_TETRAHEDRAL 1
+
+// Difference between frames, in standard deviations of the noise, above which temporal
+// denoising treats a pixel as changed rather than noisy. Its weight for the previous frame
+// ramps down over the last standard deviation below the threshold.
+#define DENOISE_THRESHOLD_SIGMAS 3.f
+// Noise is modeled as shot noise growing with the square root of luma, with a floor below
+// DENOISE_MIN_LUMA for the read noise
+#define DENOISE_MIN_LUMA 16.f
+
+// Standard deviation of the luma differences across which chroma denoising still averages.
+// Larger differences are treated as edges.
+#define CHROMA_DENOISE_LUMA_SIGMA 8.f
+
+// Farthest the sharpening blur reaches, in pixels, however large gSharpenRadius is
+#define SHARPEN_MAX_EXTENT 4
+// Radius of the neighborhood whose range sharpened luma is clamped to
+#define SHARPEN_CLAMP_RADIUS 1
+
+// Shift of the dither thresholds from one frame to the next, the golden ratio conjugate, so that
+// the thresholds of consecutive frames spread evenly over [0, 1). The shift starts over every
+// DITHER_FRAME_PERIOD frames, to keep it precise.
+#define DITHER_FRAME_STEP 0.618034f
+#define DITHER_FRAME_PERIOD 1024
+
+// Split-screen shapes for gSplitShape, must match ViewfinderProcessor.SPLIT_ ints
+#define SPLIT_VERTICAL 0
+#define SPLIT_HORIZONTAL 1
+#define SPLIT_ANGLE 2
+#define SPLIT_LOUPE 3
+
+// Spread of the well-exposedness curve around mid-grey, in normalized luma
+#define FUSION_SIGMA 0.2f
+// Keeps flat, grey or badly exposed pixels from getting a zero weight in both frames
+#define FUSION_EPSILON (1.f / 255.f)
+
+// Saturation difference, in abs(U-128)+abs(V-128) units, over which the saturation boosting
+// merge crossfades between the two frames' chroma instead of switching hard
+#define SATURATION_BLEND_RANGE 16.f
+
+// Transfer functions for gTransferFunction, must match ViewfinderProcessor.TRANSFER_FUNCTION_ ints
+#define TRANSFER_FUNCTION_GAMMA 0
+#define TRANSFER_FUNCTION_HLG 1
+#define TRANSFER_FUNCTION_PQ 2
+
+// Tone mapping operators for gToneMapOperator, must match ViewfinderProcessor.TONEMAP_ ints
+#define TONEMAP_GAMMA 0
+#define TONEMAP_REINHARD 1
+#define TONEMAP_FILMIC 2
+#define TONEMAP_LOG 3
+#define TONEMAP_LOCAL 4
+
+// Size in pixels of one cell of the local tone mapping luminance grid, must match
+// ViewfinderProcessor.GRID_TILE_SIZE
+#define GRID_TILE_SIZE 32
+// Only every GRID_SAMPLE_STEP'th pixel in each direction contributes to its grid cell average
+#define GRID_SAMPLE_STEP 4
+// Floor for the scaled radiance before taking its log, to keep black from going to -inf
+#define GRID_MIN_LUMINANCE 1e-4f
+
+// Luma range in which the exposure-normalized luma of two frames can be compared, for motion
+// detection and alignment; outside of it at least one of them is too noisy or clipped to say
+#define RELIABLE_MIN_LUMA 16
+#define RELIABLE_MAX_LUMA 240
+
+// P010 samples keep their 10 bits in the top of 16; dividing by P010_SCALE brings them to the
+// 8-bit range, with the two extra bits as a fraction
+#define P010_SCALE 256.f
+// 10-bit frames quantize the shadows P010_SHADOW_GAIN times as finely as 8-bit ones, so their
+// shadows are trusted down to that many times darker luma
+#define P010_SHADOW_GAIN 4.f
+
+// Frame alignment searches offsets from -ALIGN_SEARCH_RADIUS to ALIGN_SEARCH_RADIUS candidates
+// in each direction, ALIGN_SEARCH_STEP pixels apart, then refines the best of them by searching
+// ALIGN_REFINE_RADIUS one pixel steps around it. Both compare the frames on the same sparse grid
+// of samples, ALIGN_SAMPLE_COLUMNS across the frame. Must match ViewfinderProcessor.ALIGN_
+// constants.
+#define ALIGN_SEARCH_RADIUS 8
+#define ALIGN_SEARCH_STEP 2
+#define ALIGN_REFINE_RADIUS 1
+#define ALIGN_SAMPLE_COLUMNS 80
+// Fewest samples a candidate has to be compared on, so that a candidate isn't picked for the
+// handful of samples that happen to be well exposed in both frames
+#define ALIGN_MIN_SAMPLES 64
+// Cost of an offset at which no samples could be compared
+#define ALIGN_INVALID_COST 1000.f
+
+// Largest boost applied to chroma when tone mapping brightens a pixel
+#define TONEMAP_MAX_CHROMA_SCALE 2.f
+
+// History frames are stored as ushort4 (Y, U, V, fusion contrast) with 16 bits per channel.
+// 8-bit values are scaled by HISTORY_SCALE so that 255 maps to 65535.
+#define HISTORY_SCALE 257.f
+
+// Approximate transfer function of SDR camera output, used to linearize its luma, and of the
+// display the output is shown on
+#define TRANSFER_GAMMA 2.2f
+// Constants of the BT.2100 HLG OETF, and the linear value of its reference white at 75% signal
+#define HLG_A 0.17883277f
+#define HLG_B 0.28466892f
+#define HLG_C 0.55991073f
+#define HLG_REFERENCE_WHITE 0.26496256f
+// Constants of the SMPTE ST 2084 (PQ) EOTF, and its reference white of 203 out of 10000 nits
+#define PQ_M1 0.1593017578125f
+#define PQ_M2 78.84375f
+#define PQ_C1 0.8359375f
+#define PQ_C2 18.8515625f
+#define PQ_C3 18.6875f
+#define PQ_REFERENCE_WHITE 0.0203f
+// Smallest confidence any luma value gets in the radiance merge
+#define RADIANCE_MIN_WEIGHT 0.01f
+
+// The countHistograms kernel counts HISTOGRAM_BINS values in each of HISTOGRAM_COUNT
+// histograms, laid out one after the other in the order of the HISTOGRAM_ indices. Must match
+// the ViewfinderProcessor.HISTOGRAM_ constants.
+#define HISTOGRAM_BINS 256
+#define HISTOGRAM_FRAME_LUMA 0
+#define HISTOGRAM_OUTPUT_LUMA 1
+#define HISTOGRAM_RED 2
+#define HISTOGRAM_GREEN 3
+#define HISTOGRAM_BLUE 4
+#define HISTOGRAM_COUNT 5
+
+// Pixel counts of the histograms, bound to an allocation of HISTOGRAM_COUNT * HISTOGRAM_BINS
+// counters
+uint32_t *gHistogramCounts;
+
+/*
+ * Luma of the current frame in the full 8-bit range, with a fractional part for P010 input.
+ * Limited range luma is expanded here.
+ */
+static float readLuma(uint32_t x, uint32_t y) {
+    float luma;
+    if (gInputP010 == 1) {
+        luma = rsGetElementAt_ushort(gCurrentLumaP010, x, y) / P010_SCALE;
+    } else {
+        luma = rsGetElementAtYuv_uchar_Y(gCurrentFrame, x, y);
+    }
+    if (gLimitedRange == 1) {
+        luma = clamp((luma - 16.f) * (255.f / 219.f), 0.f, 255.f);
+    }
+    return luma;
+}
+
+/*
+ * Chroma sample of the current frame in the full 8-bit range, in chroma coordinates. Limited
+ * range chroma is expanded here.
+ */
+static float2 readChromaSample(uint32_t x, uint32_t y) {
+    float2 chroma;
+    if (gInputP010 == 1) {
+        chroma = convert_float2(rsGetElementAt_ushort2(gCurrentChromaP010, x, y)) / P010_SCALE;
+    } else {
+        chroma.x = rsGetElementAtYuv_uchar_U(gCurrentFrame, 2 * x, 2 * y);
+        chroma.y = rsGetElementAtYuv_uchar_V(gCurrentFrame, 2 * x, 2 * y);
+    }
+    if (gLimitedRange == 1) {
+        chroma = clamp((chroma - 128.f) * (255.f / 224.f) + 128.f, 0.f, 255.f);
+    }
+    return chroma;
+}
+
+/*
+ * Absolute value of the 4-neighbor Laplacian of the luma of the current frame, clamped to the
+ * 8-bit range. Used as the contrast measure for exposure fusion.
+ */
+static float lumaContrast(uint32_t x, uint32_t y) {
+    uint32_t maxX = rsAllocationGetDimX(gHistory) - 1;
+    uint32_t maxY = rsAllocationGetDimY(gHistory) - 1;
+
+    float center = readLuma(x, y);
+    float laplacian = readLuma(x > 0 ? x - 1 : 0, y) + readLuma(min(x + 1, maxX), y) +
+            readLuma(x, y > 0 ? y - 1 : 0) + readLuma(x, min(y + 1, maxY)) - 4.f * center;
+
+    return min(fabs(laplacian), 255.f);
+}
+
+/*
+ * Exposure fusion weight of a single YUV pixel with its contrast in alpha, after Mertens et al.:
+ * the product of local contrast, color saturation and well-exposedness (closeness of luma to
+ * mid-grey).
+ */
+static float fusionWeight(float4 pixel) {
+    float luma = pixel.r / 255.f - 0.5f;
+    float wellExposedness = exp(-luma * luma / (2.f * FUSION_SIGMA * FUSION_SIGMA));
+
+    float saturation = length(pixel.gb - 128.f) / 128.f;
+
+    return (pixel.a / 255.f + FUSION_EPSILON) * (saturation + FUSION_EPSILON) *
+            wellExposedness + FUSION_EPSILON * FUSION_EPSILON;
+}
+
+/*
+ * Linear light of a luma value of the camera output, going by its transfer function. HDR
+ * transfer functions are scaled so that their reference white maps to 1, like the SDR white.
+ */
+static float linearize(float luma) {
+    if (gTransferFunction == TRANSFER_FUNCTION_GAMMA) {
+        return pow(luma / 255.f, TRANSFER_GAMMA);
+    }
+
+    float signal = clamp(luma / 255.f, 0.f, 1.f);
+    if (gTransferFunction == TRANSFER_FUNCTION_HLG) {
+        float linear = signal <= 0.5f ? signal * signal / 3.f :
+                (exp((signal - HLG_C) / HLG_A) + HLG_B) / 12.f;
+        return linear / HLG_REFERENCE_WHITE;
+    }
+    float power = pow(signal, 1.f / PQ_M2);
+    float linear = pow(max(power - PQ_C1, 0.f) / (PQ_C2 - PQ_C3 * power), 1.f / PQ_M1);
+    return linear / PQ_REFERENCE_WHITE;
+}
+
+/*
+ * Output luma of a linear value, for the SDR display the viewfinder is shown on
+ */
+static float delinearize(float linear) {
+    return pow(clamp(linear, 0.f, 1.f), 1.f / TRANSFER_GAMMA) * 255.f;
+}
+
+/*
+ * Hat-shaped confidence of a luma value as a radiance estimate: highest at mid-grey,
+ * falling off towards the noisy shadows and the clipped highlights. With 10-bit input the
+ * shadow side rises P010_SHADOW_GAIN times as steeply.
+ */
+static float radianceWeight(float luma) {
+    float shadowWeight = 2.f * luma / 255.f;
+    if (gInputP010 == 1) {
+        shadowWeight *= P010_SHADOW_GAIN;
+    }
+    float highlightWeight = 2.f - 2.f * luma / 255.f;
+    return max(min(min(shadowWeight, highlightWeight), 1.f), RADIANCE_MIN_WEIGHT);
+}
+
+/*
+ * Global tone mapping of a radiance value to linear display luminance in [0, 1].
+ * The radiance is first scaled so that mid-grey at the reference exposure maps to the key value.
+ */
+static float toneMap(float radiance, float referenceScale) {
+    float scaled = radiance * referenceScale * gToneMapKey / 0.18f;
+    float white = max(gToneMapWhite, 1e-3f);
+
+    float display;
+    if (gToneMapOperator == TONEMAP_REINHARD || gToneMapOperator == TONEMAP_LOCAL) {
+        // Extended Reinhard, reaches 1 exactly at the white point
+        display = scaled * (1.f + scaled / (white * white)) / (1.f + scaled);
+    } else if (gToneMapOperator == TONEMAP_FILMIC) {
+        // Narkowicz's fit of the ACES filmic curve, normalized to the white point
+        float acesScaled = scaled * 0.6f;
+        float curve = acesScaled * (2.51f * acesScaled + 0.03f) /
+                (acesScaled * (2.43f * acesScaled + 0.59f) + 0.14f);
+        float acesWhite = white * 0.6f;
+        float curveWhite = acesWhite * (2.51f * acesWhite + 0.03f) /
+                (acesWhite * (2.43f * acesWhite + 0.59f) + 0.14f);
+        display = curve / curveWhite;
+    } else if (gToneMapOperator == TONEMAP_LOG) {
+        display = log(1.f + scaled) / log(1.f + white);
+    } else {
+        // Plain exposure scaling, clipping at the white point
+        display = scaled / white;
+    }
+    return clamp(display, 0.f, 1.f);
+}
+
+/*
+ * Scale chroma along with a change in luma, so that darkened highlights don't end up
+ * oversaturated
+ */
+static float2 followLuma(float2 chroma, float fromLuma, float toLuma) {
+    float chromaScale = min(toLuma / max(fromLuma, 1.f), TONEMAP_MAX_CHROMA_SCALE);
+    return clamp((chroma - 128.f) * chromaScale + 128.f, 0.f, 255.f);
+}
+
+/*
+ * Tone map a radiance merge result, stored as (radiance, U, V, luma of the input frames),
+ * back to a displayable YUV pixel.
+ */
+static float4 toneMapPixel(float4 merged) {
+    float4 pixel;
+    pixel.r = delinearize(toneMap(merged.x, gReferenceScale));
+    pixel.gb = followLuma(merged.yz, merged.w, pixel.r);
+    pixel.a = 255.f;
+    return pixel;
+}
+
+/*
+ * Convert full range YUV to RGB with the selected color matrix, without clamping or rounding
+ */
+static float3 yuvToRgb(float4 yuv) {
+    float luma = yuv.r;
+    float2 chroma = yuv.gb - 128.f;
+
+    float3 rgb;
+    rgb.r = luma + gColorMatrixRV * chroma.y;
+    rgb.g = luma + gColorMatrixGU * chroma.x + gColorMatrixGV * chroma.y;
+    rgb.b = luma + gColorMatrixBU * chroma.x;
+    return rgb;
+}
+
+/*
+ * Threshold at which an output value rounds up to the next 8-bit level: one half, or the dither
+ * texture's value for the pixel in the current frame
+ */
+static float ditherThreshold(uint32_t x, uint32_t y) {
+    if (gDither == 0) return 0.5f;
+
+    uint32_t width = rsAllocationGetDimX(gDitherTexture);
+    uint32_t height = rsAllocationGetDimY(gDitherTexture);
+    float threshold = rsGetElementAt_float(gDitherTexture, x % width, y % height) +
+            (gFrameCounter % DITHER_FRAME_PERIOD) * DITHER_FRAME_STEP;
+    return threshold - floor(threshold);
+}
+
+/*
+ * Quantize an RGB color in the 8-bit range to the output format
+ */
+static uchar4 quantize(float3 rgb, uint32_t x, uint32_t y) {
+    uchar4 out;
+    out.rgb = convert_uchar3(clamp(rgb + ditherThreshold(x, y), 0.f, 255.f));
+    out.a = 255;
+    return out;
+}
+
+/*
+ * Color of the false color map for a full range luma value
+ */
+static uchar4 falseColor(float luma) {
+    float last = rsAllocationGetDimX(gFalseColorLut) - 1;
+    uint32_t index = (uint32_t) clamp(luma * last / 255.f + 0.5f, 0.f, last);
+
+    uchar4 out = rsGetElementAt_uchar4(gFalseColorLut, index);
+    out.a = 255;
+    return out;
+}
+
+/*
+ * Look an RGB color up in the color grading table. Trilinear interpolation blends the 8 points
+ * of the table around the color; tetrahedral interpolation only the 4 points of the tetrahedron
+ * of that cube the color is in, which keeps neutral colors neutral.
+ */
+static float3 gradeColor(float3 rgb) {
+    float last = rsAllocationGetDimX(gColorLut) - 1;
+    float3 position = (clamp(rgb, 0.f, 255.f) / 255.f - gColorLutDomainMin) /
+            (gColorLutDomainMax - gColorLutDomainMin) * last;
+    position = clamp(position, 0.f, last);
+
+    uint32_t r0 = (uint32_t) position.x;
+    uint32_t g0 = (uint32_t) position.y;
+    uint32_t b0 = (uint32_t) position.z;
+    uint32_t r1 = min(r0 + 1, (uint32_t) last);
+    uint32_t g1 = min(g0 + 1, (uint32_t) last);
+    uint32_t b1 = min(b0 + 1, (uint32_t) last);
+    float fr = position.x - r0;
+    float fg = position.y - g0;
+    float fb = position.z - b0;
+
+    float3 c000 = rsGetElementAt_float4(gColorLut, r0, g0, b0).rgb;
+    float3 c111 = rsGetElementAt_float4(gColorLut, r1, g1, b1).rgb;
+    float3 color;
+    if (gColorLutInterpolation == LUT_TETRAHEDRAL) {
+        if (fr > fg) {
+            if (fg > fb) {
+                float3 c100 = rsGetElementAt_float4(gColorLut, r1, g0, b0).rgb;
+                float3 c110 = rsGetElementAt_float4(gColorLut, r1, g1, b0).rgb;
+                color = c000 + fr * (c100 - c000) + fg * (c110 - c100) + fb * (c111 - c110);
+            } else if (fr > fb) {
+                float3 c100 = rsGetElementAt_float4(gColorLut, r1, g0, b0).rgb;
+                float3 c101 = rsGetElementAt_float4(gColorLut, r1, g0, b1).rgb;
+                color = c000 + fr * (c100 - c000) + fb * (c101 - c100) + fg * (c111 - c101);
+            } else {
+                float3 c001 = rsGetElementAt_float4(gColorLut, r0, g0, b1).rgb;
+                float3 c101 = rsGetElementAt_float4(gColorLut, r1, g0, b1).rgb;
+                color = c000 + fb * (c001 - c000) + fr * (c101 - c001) + fg * (c111 - c101);
+            }
+        } else {
+            if (fb > fg) {
+                float3 c001 = rsGetElementAt_float4(gColorLut, r0, g0, b1).rgb;
+                float3 c011 = rsGetElementAt_float4(gColorLut, r0, g1, b1).rgb;
+                color = c000 + fb * (c001 - c000) + fg * (c011 - c001) + fr * (c111 - c011);
+            } else if (fb > fr) {
+                float3 c010 = rsGetElementAt_float4(gColorLut, r0, g1, b0).rgb;
+                float3 c011 = rsGetElementAt_float4(gColorLut, r0, g1, b1).rgb;
+                color = c000 + fg * (c010 - c000) + fb * (c011 - c010) + fr * (c111 - c011);
+            } else {
+                float3 c010 = rsGetElementAt_float4(gColorLut, r0, g1, b0).rgb;
+                float3 c110 = rsGetElementAt_float4(gColorLut, r1, g1, b0).rgb;
+                color = c000 + fg * (c010 - c000) + fr * (c110 - c010) + fb * (c111 - c110);
+            }
+        }
+    } else {
+        float3 c100 = rsGetElementAt_float4(gColorLut, r1, g0, b0).rgb;
+        float3 c010 = rsGetElementAt_float4(gColorLut, r0, g1, b0).rgb;
+        float3 c110 = rsGetElementAt_float4(gColorLut, r1, g1, b0).rgb;
+        float3 c001 = rsGetElementAt_float4(gColorLut, r0, g0, b1).rgb;
+        float3 c101 = rsGetElementAt_float4(gColorLut, r1, g0, b1).rgb;
+        float3 c011 = rsGetElementAt_float4(gColorLut, r0, g1, b1).rgb;
+        color = mix(mix(mix(c000, c100, fr), mix(c010, c110, fr), fg),
+                mix(mix(c001, c101, fr), mix(c011, c111, fr), fg), fb);
+    }
+
+    return color * 255.f;
+}
+
+/*
+ * Final color of an output pixel: the false color map of its luma, or its RGB conversion,
+ * color graded if enabled
+ */
+static uchar4 outputColor(float4 yuv, uint32_t x, uint32_t y) {
+    if (gFalseColor == 1) {
+        return falseColor(yuv.r);
+    }
+    float3 rgb = yuvToRgb(yuv);
+    if (gColorGrading == 1) {
+        rgb = gradeColor(rgb);
+    }
+    return quantize(rgb, x, y);
+}
+
+/*
+ * Read the latest frame as YUV in the 8-bit range, with full contrast in alpha.
+ */
+static float4 readCurrentPixel(uint32_t x, uint32_t y) {
+    float4 pixel;
+    pixel.r = readLuma(x, y);
+    if (gChromaDenoise == 1) {
+        pixel.gb = rsGetElementAt_float2(gDenoisedChroma, x / 2, y / 2);
+    } else {
+        pixel.gb = readChromaSample(x / 2, y / 2);
+    }
+    pixel.a = 255.f;
+    return pixel;
+}
+
+/*
+ * History slot of the frame the given number of frames before the current one.
+ */
+static int historySlot(int framesAgo) {
+    return (gHistorySlot + HISTORY_LENGTH - framesAgo) % HISTORY_LENGTH;
+}
+
+/*
+ * Exposure time times gain of the frame in a history slot, dividing luma by which gives radiance.
+ */
+static float slotScale(int slot) {
+    return gSlotExposure[slot] * gSlotGain[slot];
+}
+
+/*
+ * Whether a history slot holds a frame with a known exposure. Slots are empty for the first
+ * frames after the processor starts, and their radiance can't be estimated.
+ */
+static bool slotFilled(int slot) {
+    return gSlotBracketIndex[slot] >= 0 && slotScale(slot) > 0.f;
+}
+
+/*
+ * Read an earlier frame at the current frame's coordinates, compensating for camera motion
+ * between the frames and clamping to the frame edges.
+ */
+static float4 readHistoryPixel(int slot, uint32_t x, uint32_t y) {
+    int maxX = rsAllocationGetDimX(gHistory) - 1;
+    int maxY = rsAllocationGetDimY(gHistory) - 1;
+    ushort4 stored = rsGetElementAt_ushort4(gHistory,
+            clamp((int) x + gSlotOffsetX[slot], 0, maxX),
+            clamp((int) y + gSlotOffsetY[slot], 0, maxY), slot);
+    return convert_float4(stored) / HISTORY_SCALE;
+}
+
+/*
+ * History slot of the latest frame taken at an exposure of the bracket, or the current frame's
+ * slot if there is none
+ */
+static int latestSlotOfExposure(int bracketIndex) {
+    for (int framesAgo = 0; framesAgo < HISTORY_LENGTH; framesAgo++) {
+        int slot = historySlot(framesAgo);
+        if (gSlotBracketIndex[slot] == bracketIndex) {
+            return slot;
+        }
+    }
+    return gHistorySlot;
+}
+
+/*
+ * Temporally denoise a pixel of the current frame by recursively averaging it with the previous
+ * frame of the same exposure, unless they differ by more than their noise explains
+ */
+static float4 temporalDenoise(float4 pixel, uint32_t x, uint32_t y) {
+    int bracketIndex = gSlotBracketIndex[gHistorySlot];
+    for (int framesAgo = 1; framesAgo < HISTORY_LENGTH; framesAgo++) {
+        int slot = historySlot(framesAgo);
+        if (gSlotBracketIndex[slot] != bracketIndex) continue;
+        if (slotScale(slot) != slotScale(gHistorySlot)) break;
+
+        float4 prevPixel = readHistoryPixel(slot, x, y);
+        float noise = gTemporalDenoiseNoise *
+                sqrt(max(pixel.r, DENOISE_MIN_LUMA) / 128.f * gSlotGain[gHistorySlot]);
+        // Chroma is compared too, so that objects of about the same brightness as what they
+        // move over don't leave trails of their color
+        float3 differences = fabs(pixel.rgb - prevPixel.rgb);
+        float difference = fmax(differences.r, fmax(differences.g, differences.b));
+        float weight = gTemporalDenoiseStrength *
+                clamp(DENOISE_THRESHOLD_SIGMAS - difference / noise, 0.f, 1.f);
+        pixel.rgb = mix(pixel.rgb, prevPixel.rgb, weight);
+        break;
+    }
+    return pixel;
+}
+
+static float4 readPrevPixel(uint32_t x, uint32_t y) {
+    return readHistoryPixel(historySlot(1), x, y);
+}
+
+/*
+ * Store a pixel of the current frame at full precision, to be read back by later frames.
+ */
+static void storeCurrentPixel(float4 pixel, uint32_t x, uint32_t y) {
+    float4 scaled = clamp(pixel * HISTORY_SCALE + 0.5f, 0.f, 65535.f);
+    rsSetElementAt_ushort4(gHistory, convert_ushort4(scaled), x, y, gHistorySlot);
+}
+
+/*
+ * Largest motion mask value in the 3x3 neighborhood, so that the fallback to a single frame
+ * also covers the edges of moving objects.
+ */
+static float dilatedMotion(uint32_t x, uint32_t y) {
+    uint32_t maxX = rsAllocationGetDimX(gMotionMask) - 1;
+    uint32_t maxY = rsAllocationGetDimY(gMotionMask) - 1;
+
+    uchar motion = 0;
+    for (uint32_t my = y > 0 ? y - 1 : 0; my <= min(y + 1, maxY); my++) {
+        for (uint32_t mx = x > 0 ? x - 1 : 0; mx <= min(x + 1, maxX); mx++) {
+            motion = max(motion, rsGetElementAt_uchar(gMotionMask, mx, my));
+        }
+    }
+    return motion / 255.f;
+}
+
+/*
+ * False color rendering of the motion mask over a greyscale image, from yellow for slight
+ * motion to red for certain motion.
+ */
+static uchar4 motionFalseColor(uchar4 rgb, float motion) {
+    float grey = dot(convert_float3(rgb.rgb), (float3) {0.299f, 0.587f, 0.114f});
+    float3 heat = (float3) {255.f, 255.f * (1.f - motion), 0.f};
+    float3 color = mix((float3) {grey, grey, grey}, heat,
+            motion > 0.f ? 0.5f + 0.5f * motion : 0.f);
+
+    uchar4 out;
+    out.rgb = convert_uchar3(clamp(color + 0.5f, 0.f, 255.f));
+    out.a = 255;
+    return out;
+}
+
+/*
+ * Whether a pixel is in the part of the split screen that shows even frames
+ */
+static bool inEvenSplit(uint32_t x, uint32_t y) {
+    float2 offset = {(float) x - gSplitX, (float) y - gSplitY};
+    if (gSplitShape == SPLIT_HORIZONTAL) {
+        return offset.y < 0.f;
+    } else if (gSplitShape == SPLIT_ANGLE) {
+        return dot(offset, gSplitNormal) < 0.f;
+    } else if (gSplitShape == SPLIT_LOUPE) {
+        return dot(offset, offset) < gLoupeRadius * gLoupeRadius;
+    }
+    return offset.x < 0.f;
+}
+
+/*
+ * Rescale a pixel of the frame in a history slot from its own exposure to the reference
+ * exposure of the bracket
+ */
+static float4 normalizeExposure(float4 pixel, int slot) {
+    float4 normalized;
+    normalized.r = delinearize(linearize(pixel.r) * gReferenceScale / slotScale(slot));
+    normalized.gb = followLuma(pixel.gb, pixel.r, normalized.r);
+    normalized.a = pixel.a;
+    return normalized;
+}
+
+/*
+ * Mark clipped highlights with zebra stripes and crushed shadows with a tint, going by the
+ * full range luma of the pixel
+ */
+static uchar4 exposureWarning(uchar4 rgb, float luma, uint32_t x, uint32_t y) {
+    if (luma >= gZebraHighThreshold) {
+        if ((x + y + gFrameCounter) % ZEBRA_PERIOD < ZEBRA_PERIOD / 2) {
+            rgb.rgb = 0;
+        }
+    } else if (luma <= gZebraLowThreshold) {
+        float3 color = mix(convert_float3(rgb.rgb), SHADOW_WARNING_COLOR,
+                SHADOW_WARNING_OPACITY);
+        rgb.rgb = convert_uchar3(color + 0.5f);
+    }
+    return rgb;
+}
+
+/*
+ * Sobel gradient magnitude of the luma of the current frame, scaled so that a sharp step between
+ * two flat areas measures the difference in luma across it
+ */
+static float lumaGradient(uint32_t x, uint32_t y) {
+    uint32_t x0 = x > 0 ? x - 1 : 0;
+    uint32_t y0 = y > 0 ? y - 1 : 0;
+    uint32_t x1 = min(x + 1, rsAllocationGetDimX(gHistory) - 1);
+    uint32_t y1 = min(y + 1, rsAllocationGetDimY(gHistory) - 1);
+
+    float topLeft = readLuma(x0, y0);
+    float top = readLuma(x, y0);
+    float topRight = readLuma(x1, y0);
+    float left = readLuma(x0, y);
+    float right = readLuma(x1, y);
+    float bottomLeft = readLuma(x0, y1);
+    float bottom = readLuma(x, y1);
+    float bottomRight = readLuma(x1, y1);
+
+    float2 gradient;
+    gradient.x = (topRight + 2.f * right + bottomRight) - (topLeft + 2.f * left + bottomLeft);
+    gradient.y = (bottomLeft + 2.f * bottom + bottomRight) - (topLeft + 2.f * top + topRight);
+    return length(gradient) * 0.25f;
+}
+
+/*
+ * Draw focus peaking over a pixel on an in-focus edge of the current frame
+ */
+static uchar4 focusPeaking(uchar4 rgb, uint32_t x, uint32_t y) {
+    if (lumaGradient(x, y) >= gFocusPeakingThreshold) {
+        rgb.rgb = convert_uchar3(gFocusPeakingColor + 0.5f);
+    }
+    return rgb;
+}
+
+/*
+ * Mean luma of the current frame over the 2x2 pixels sharing a chroma sample
+ */
+static float chromaSampleLuma(uint32_t x, uint32_t y) {
+    return (readLuma(2 * x, 2 * y) + readLuma(2 * x + 1, 2 * y) +
+            readLuma(2 * x, 2 * y + 1) + readLuma(2 * x + 1, 2 * y + 1)) * 0.25f;
+}
+
+/*
+ * Whether two luma values are both far enough from black and white for their
+ * exposure-normalized values to be compared.
+ */
+static bool reliableLumaPair(float curLuma, float prevLuma) {
+    float minLuma = gInputP010 == 1 ? RELIABLE_MIN_LUMA / P010_SHADOW_GAIN : RELIABLE_MIN_LUMA;
+    return curLuma >= minLuma && curLuma <= RELIABLE_MAX_LUMA &&
+            prevLuma >= minLuma && prevLuma <= RELIABLE_MAX_LUMA;
+}
+
+/*
+ * Keep a merged pixel for sharpenLuma, with the luma exposure warnings are drawn for in alpha,
+ * and its luma on its own for the neighborhoods sharpenLuma reads
+ */
+static void storeMergedPixel(float4 pixel, float warningLuma, uint32_t x, uint32_t y) {
+    rsSetElementAt_float(gMergedLuma, pixel.r, x, y);
+    pixel.a = warningLuma;
+    rsSetElementAt_float4(gMerged, pixel, x, y);
+}
+
+/*
+ * Joint bilateral filter of the chroma of the current frame, guided by its luma. Run over
+ * gDenoisedChroma, one cell per chroma sample, before mergeHdrFrames.
+ */
+float2 __attribute__((kernel)) denoiseChroma(uint32_t x, uint32_t y) {
+    int maxX = rsAllocationGetDimX(gDenoisedChroma) - 1;
+    int maxY = rsAllocationGetDimY(gDenoisedChroma) - 1;
+    int radius = gChromaDenoiseRadius;
+    float2 center = readChromaSample(x, y);
+    if (radius < 1) return center;
+
+    // Gaussian falloff with distance, reaching two standard deviations at the radius
+    float spatialSigma = radius * 0.5f;
+    float spatialScale = -0.5f / (spatialSigma * spatialSigma);
+    float rangeScale = -0.5f / (CHROMA_DENOISE_LUMA_SIGMA * CHROMA_DENOISE_LUMA_SIGMA);
+    float centerLuma = chromaSampleLuma(x, y);
+
+    float2 sum = {0.f, 0.f};
+    float weightSum = 0.f;
+    for (int dy = -radius; dy <= radius; dy++) {
+        for (int dx = -radius; dx <= radius; dx++) {
+            int sx = clamp((int) x + dx, 0, maxX);
+            int sy = clamp((int) y + dy, 0, maxY);
+            float lumaDifference = chromaSampleLuma(sx, sy) - centerLuma;
+            float weight = exp(spatialScale * (dx * dx + dy * dy) +
+                    rangeScale * lumaDifference * lumaDifference);
+            sum += weight * readChromaSample(sx, sy);
+            weightSum += weight;
+        }
+    }
+    return mix(center, sum / weightSum, gChromaDenoiseStrength);
+}
+
+/*
+ * Motion mask between the current and previous frames, from comparing their luma after
+ * normalizing out the exposure difference. Run into gMotionMask before mergeHdrFrames,
+ * which then reads the dilated mask.
+ */
+uchar __attribute__((kernel)) detectMotion(uint32_t x, uint32_t y) {
+    float curLuma = readLuma(x, y);
+    float prevLuma = readPrevPixel(x, y).r;
+
+    if (!reliableLumaPair(curLuma, prevLuma)) {
+        return 0;
+    }
+
+    float curRadiance = linearize(curLuma) / slotScale(gHistorySlot);
+    float prevRadiance = linearize(prevLuma) / slotScale(historySlot(1));
+    float difference = fabs(log(curRadiance / prevRadiance));
+
+    // Ramp up from the threshold to twice the threshold, for a soft mask edge
+    float motion = clamp(difference / gDeghostThreshold - 1.f, 0.f, 1.f);
+    return (uchar) (motion * 255.f + 0.5f);
+}
+
+/*
+ * Alignment cost of one candidate offset of the previous frame: the mean absolute log ratio of
+ * the exposure-normalized luma of the two frames over a sparse grid of well-exposed samples, or
+ * ALIGN_INVALID_COST if too few samples could be compared.
+ * Run over a (2 * gAlignRadius + 1)^2 allocation, one cell per candidate.
+ */
+float __attribute__((kernel)) alignmentCost(uint32_t x, uint32_t y) {
+    int prevSlot = historySlot(1);
+    if (!slotFilled(prevSlot)) {
+        return ALIGN_INVALID_COST;
+    }
+
+    int offsetX = gAlignCenterX + ((int) x - gAlignRadius) * gAlignStep;
+    int offsetY = gAlignCenterY + ((int) y - gAlignRadius) * gAlignStep;
+    int margin = ALIGN_SEARCH_RADIUS * ALIGN_SEARCH_STEP + ALIGN_REFINE_RADIUS;
+    int width = rsAllocationGetDimX(gHistory);
+    int height = rsAllocationGetDimY(gHistory);
+    float curScale = slotScale(gHistorySlot);
+    float prevScale = slotScale(prevSlot);
+
+    float cost = 0.f;
+    int count = 0;
+    int sampleStep = max(width / ALIGN_SAMPLE_COLUMNS, 1);
+    for (int sy = margin; sy < height - margin; sy += sampleStep) {
+        for (int sx = margin; sx < width - margin; sx += sampleStep) {
+            float curLuma = readLuma(sx, sy);
+            float prevLuma = rsGetElementAt_ushort4(gHistory,
+                    sx + offsetX, sy + offsetY, prevSlot).r / HISTORY_SCALE;
+            if (!reliableLumaPair(curLuma, prevLuma)) {
+                continue;
+            }
+            cost += fabs(log((linearize(curLuma) / curScale) /
+                    (linearize(prevLuma) / prevScale)));
+            count++;
+        }
+    }
+    return count >= ALIGN_MIN_SAMPLES ? cost / count : ALIGN_INVALID_COST;
+}
+
+uchar4 __attribute__((kernel)) mergeHdrFrames(uint32_t x, uint32_t y) {
+
+    // Read in pixel values from latest and previous frames - YUV color space
+
+    float4 curPixel = readCurrentPixel(x, y);
+    float4 prevPixel = readPrevPixel(x, y);
+    if (gTemporalDenoise == 1) {
+        curPixel = temporalDenoise(curPixel, x, y);
+    }
+
+    // Where there is motion, fall back to whichever frame is better exposed
+    float motion = 0.f;
+    float4 bestPixel = curPixel;
+    int bestSlot = gHistorySlot;
+    if (gDeghost == 1 && gMergeMode != MERGE_NONE) {
+        motion = dilatedMotion(x, y);
+        if (slotFilled(historySlot(1)) &&
+                radianceWeight(prevPixel.r) > radianceWeight(curPixel.r)) {
+            bestPixel = prevPixel;
+            bestSlot = historySlot(1);
+        }
+    }
+
+    float4 mergedPixel;
+    int splitSlot = -1;
+    if (gMergeMode == MERGE_FUSION) {
+        // Per-pixel exposure fusion. The contrast of each frame is kept in the alpha channel of
+        // the stored previous frame, so its neighbors never need to be read back.
+        curPixel.a = lumaContrast(x, y);
+
+        float curWeight = fusionWeight(curPixel);
+        float prevWeight = fusionWeight(prevPixel);
+        float blend = curWeight / (curWeight + prevWeight);
+
+        mergedPixel = mix(prevPixel, curPixel, blend);
+    } else if (gMergeMode == MERGE_SATURATION) {
+        // Color saturation boosting merge: average luma, but favor the chroma of whichever
+        // frame is more saturated, crossfading near equal saturation to avoid speckling
+        mergedPixel.r = (curPixel.r + prevPixel.r) * 0.5f;
+
+        float saturationCurrent = fabs(curPixel.g - 128.f) + fabs(curPixel.b - 128.f);
+        float saturationPrev = fabs(prevPixel.g - 128.f) + fabs(prevPixel.b - 128.f);
+        float blend = clamp(0.5f + (saturationCurrent - saturationPrev) /
+                (2.f * SATURATION_BLEND_RANGE), 0.f, 1.f);
+
+        mergedPixel.gb = mix(prevPixel.gb, curPixel.gb, blend);
+        mergedPixel.a = 255.f;
+    } else if (gMergeMode == MERGE_RADIANCE) {
+        // Estimate scene radiance from each frame by dividing its linearized luma by its
+        // exposure, then combine the two estimates weighted by their confidence. Until there is
+        // a previous frame, the current one is used alone.
+        int prevSlot = historySlot(1);
+        bool prevFilled = slotFilled(prevSlot);
+        float curRadiance = linearize(curPixel.r) / slotScale(gHistorySlot);
+        float prevRadiance = prevFilled ? linearize(prevPixel.r) / slotScale(prevSlot) : 0.f;
+
+        float curWeight = radianceWeight(curPixel.r);
+        float prevWeight = prevFilled ? radianceWeight(prevPixel.r) : 0.f;
+        float blend = curWeight / (curWeight + prevWeight);
+        blend = mix(blend, curWeight >= prevWeight ? 1.f : 0.f, motion);
+
+        // Keep radiance, chroma and the input luma around for the tone mapping passes
+        float4 merged;
+        merged.x = mix(prevRadiance, curRadiance, blend);
+        merged.yz = mix(prevPixel.gb, curPixel.gb, blend);
+        merged.w = mix(prevPixel.r, curPixel.r, blend);
+        rsSetElementAt_float4(gRadiance, merged, x, y);
+
+        mergedPixel = toneMapPixel(merged);
+    } else if (gMergeMode == MERGE_BRACKET) {
+        // Radiance merge over every frame of the exposure bracket that has been captured yet
+        float curWeight = radianceWeight(curPixel.r);
+        float weightSum = curWeight;
+        float4 merged;
+        merged.x = curWeight * linearize(curPixel.r) / slotScale(gHistorySlot);
+        merged.yz = curWeight * curPixel.gb;
+        merged.w = curWeight * curPixel.r;
+
+        for (int framesAgo = 1; framesAgo < gBracketLength; framesAgo++) {
+            int slot = historySlot(framesAgo);
+            if (!slotFilled(slot)) continue;
+            float4 pixel = readHistoryPixel(slot, x, y);
+            float weight = radianceWeight(pixel.r);
+            weightSum += weight;
+            merged.x += weight * linearize(pixel.r) / slotScale(slot);
+            merged.yz += weight * pixel.gb;
+            merged.w += weight * pixel.r;
+        }
+        merged /= weightSum;
+
+        // Motion is only known between the two latest frames; where there is any, use the
+        // better exposed of those alone
+        if (motion > 0.f) {
+            float4 single;
+            single.x = linearize(bestPixel.r) / slotScale(bestSlot);
+            single.yz = bestPixel.gb;
+            single.w = bestPixel.r;
+            merged = mix(merged, single, motion);
+        }
+        rsSetElementAt_float4(gRadiance, merged, x, y);
+
+        mergedPixel = toneMapPixel(merged);
+    } else if (gMergeMode == MERGE_AVERAGE) {
+        // Simple average, flattens contrast but has no artifacts
+        mergedPixel = (curPixel + prevPixel) * 0.5f;
+    } else if (gSplitScreen == 1) {
+        // Composite side by side. Each side shows the latest frame of its exposure, rather than
+        // going by the order of the frames, so that a dropped frame doesn't swap the sides.
+        splitSlot = latestSlotOfExposure(inEvenSplit(x, y) ? 0 : 1);
+        mergedPixel = splitSlot == gHistorySlot ? curPixel : readHistoryPixel(splitSlot, x, y);
     } else {
         // Straight passthrough
         mergedPixel = curPixel;
     }
 
-    // Convert YUV to RGB, JFIF transform with fixed-point math
-    // R = Y + 1.402 * (V - 128)
-    // G = Y - 0.34414 * (U - 128) - 0.71414 * (V - 128)
-    // B = Y + 1.772 * (U - 128)
+    if (motion > 0.f && gMergeMode != MERGE_RADIANCE && gMergeMode != MERGE_BRACKET) {
+        mergedPixel.rgb = mix(mergedPixel.rgb, bestPixel.rgb, motion);
+    }
 
-    int4 rgb;
-    rgb.r = mergedPixel.r +
-            mergedPixel.b * 1436 / 1024 - 179;
-    rgb.g = mergedPixel.r -
-            mergedPixel.g * 46549 / 131072 + 44 -
-            mergedPixel.b * 93604 / 131072 + 91;
-    rgb.b = mergedPixel.r +
-            mergedPixel.g * 1814 / 1024 - 227;
-    rgb.a = 255;
+    // Exposure warnings show the clipping of the output, or in split-screen mode of each frame
+    // itself, before any brightness matching
+    float warningLuma = mergedPixel.r;
+    if (splitSlot >= 0 && gSplitNormalize == 1) {
+        mergedPixel = normalizeExposure(mergedPixel, splitSlot);
+    }
 
-    // Store current pixel for next frame
-    rsSetElementAt_uchar4(gPrevFrame, curPixel, x, y);
+    // Store current pixel for the following frames. It goes into its own history slot, since
+    // other pixels may still need to read the previous frames through the alignment offsets.
+    storeCurrentPixel(curPixel, x, y);
+
+    if (gSharpen == 1) {
+        storeMergedPixel(mergedPixel, warningLuma, x, y);
+    }
 
     // Write out merged HDR result
-    uchar4 out = convert_uchar4(clamp(rgb, 0, 255));
+    uchar4 out = outputColor(mergedPixel, x, y);
+    if (gDeghost == 1 && gDeghostDebug == 1 && gMergeMode != MERGE_NONE) {
+        out = motionFalseColor(out, motion);
+    }
+    if (gZebra == 1) {
+        out = exposureWarning(out, warningLuma, x, y);
+    }
+    if (gFocusPeaking == 1) {
+        out = focusPeaking(out, x, y);
+    }
+    return out;
+}
 
+/*
+ * Average log luminance of one GRID_TILE_SIZE square of the radiance merge, scaled the same
+ * way as for tone mapping. Run over gLogLumaGrid after mergeHdrFrames.
+ */
+float __attribute__((kernel)) buildLuminanceGrid(uint32_t x, uint32_t y) {
+    uint32_t width = rsAllocationGetDimX(gRadiance);
+    uint32_t height = rsAllocationGetDimY(gRadiance);
+    float scale = gReferenceScale * gToneMapKey / 0.18f;
+
+    float sum = 0.f;
+    int count = 0;
+    for (uint32_t py = y * GRID_TILE_SIZE; py < min((y + 1) * GRID_TILE_SIZE, height);
+            py += GRID_SAMPLE_STEP) {
+        for (uint32_t px = x * GRID_TILE_SIZE; px < min((x + 1) * GRID_TILE_SIZE, width);
+                px += GRID_SAMPLE_STEP) {
+            float radiance = rsGetElementAt_float4(gRadiance, px, py).x;
+            sum += log(max(radiance * scale, GRID_MIN_LUMINANCE));
+            count++;
+        }
+    }
+    return count > 0 ? sum / count : log(gToneMapKey);
+}
+
+/*
+ * Local tone mapping of the radiance merge. The bilinearly interpolated luminance grid is the
+ * low-frequency base layer; compressing it towards the key value with a spatially varying gain
+ * keeps local contrast while bringing shadows and highlights into range.
+ * Run over gRadiance after buildLuminanceGrid, replacing the output of mergeHdrFrames.
+ */
+uchar4 __attribute__((kernel)) localToneMap(float4 merged, uint32_t x, uint32_t y) {
+    int gridMaxX = rsAllocationGetDimX(gLogLumaGrid) - 1;
+    int gridMaxY = rsAllocationGetDimY(gLogLumaGrid) - 1;
+
+    // Grid values are cell averages, so they sit at the cell centers
+    float gx = clamp((x + 0.5f) / GRID_TILE_SIZE - 0.5f, 0.f, (float) gridMaxX);
+    float gy = clamp((y + 0.5f) / GRID_TILE_SIZE - 0.5f, 0.f, (float) gridMaxY);
+    int x0 = (int) gx;
+    int y0 = (int) gy;
+    int x1 = min(x0 + 1, gridMaxX);
+    int y1 = min(y0 + 1, gridMaxY);
+    float fx = gx - x0;
+    float fy = gy - y0;
+
+    float base = mix(
+            mix(rsGetElementAt_float(gLogLumaGrid, x0, y0),
+                    rsGetElementAt_float(gLogLumaGrid, x1, y0), fx),
+            mix(rsGetElementAt_float(gLogLumaGrid, x0, y1),
+                    rsGetElementAt_float(gLogLumaGrid, x1, y1), fx),
+            fy);
+
+    merged.x *= exp(-gLocalCompression * (base - log(gToneMapKey)));
+
+    float4 pixel = toneMapPixel(merged);
+    if (gSharpen == 1) {
+        storeMergedPixel(pixel, pixel.r, x, y);
+    }
+    uchar4 out = outputColor(pixel, x, y);
+    if (gDeghost == 1 && gDeghostDebug == 1) {
+        out = motionFalseColor(out, dilatedMotion(x, y));
+    }
+    if (gZebra == 1) {
+        out = exposureWarning(out, pixel.r, x, y);
+    }
+    if (gFocusPeaking == 1) {
+        out = focusPeaking(out, x, y);
+    }
+    return out;
+}
+
+/*
+ * Unsharp mask of the luma of the merged output, keeping its chroma. Run over gMerged after the
+ * merge and any local tone mapping, replacing their output.
+ */
+uchar4 __attribute__((kernel)) sharpenLuma(float4 merged, uint32_t x, uint32_t y) {
+    int maxX = rsAllocationGetDimX(gMergedLuma) - 1;
+    int maxY = rsAllocationGetDimY(gMergedLuma) - 1;
+    int extent = clamp((int) ceil(2.f * gSharpenRadius), 1, SHARPEN_MAX_EXTENT);
+    float spatialScale = -0.5f / (gSharpenRadius * gSharpenRadius);
+
+    // The Gaussian is separable, so each tap's weight is the product of one per axis
+    float axisWeights[SHARPEN_MAX_EXTENT + 1];
+    for (int d = 0; d <= extent; d++) {
+        axisWeights[d] = exp(spatialScale * d * d);
+    }
+
+    float blurSum = 0.f;
+    float weightSum = 0.f;
+    float localMin = merged.r;
+    float localMax = merged.r;
+    for (int dy = -extent; dy <= extent; dy++) {
+        for (int dx = -extent; dx <= extent; dx++) {
+            int sx = clamp((int) x + dx, 0, maxX);
+            int sy = clamp((int) y + dy, 0, maxY);
+            float luma = rsGetElementAt_float(gMergedLuma, sx, sy);
+            float weight = axisWeights[abs(dx)] * axisWeights[abs(dy)];
+            blurSum += weight * luma;
+            weightSum += weight;
+            if (abs(dx) <= SHARPEN_CLAMP_RADIUS && abs(dy) <= SHARPEN_CLAMP_RADIUS) {
+                localMin = min(localMin, luma);
+                localMax = max(localMax, luma);
+            }
+        }
+    }
+
+    // Detail within the threshold is taken to be noise, and the threshold is taken off the rest
+    // so that the boost starts smoothly
+    float detail = merged.r - blurSum / weightSum;
+    if (detail > 0.f) {
+        detail = max(detail - gSharpenThreshold, 0.f);
+    } else {
+        detail = min(detail + gSharpenThreshold, 0.f);
+    }
+
+    float4 pixel = merged;
+    pixel.r = clamp(merged.r + gSharpenAmount * detail, localMin, localMax);
+    uchar4 out = outputColor(pixel, x, y);
+    if (gDeghost == 1 && gDeghostDebug == 1 && gMergeMode != MERGE_NONE) {
+        out = motionFalseColor(out, dilatedMotion(x, y));
+    }
+    if (gZebra == 1) {
+        out = exposureWarning(out, merged.a, x, y);
+    }
+    if (gFocusPeaking == 1) {
+        out = focusPeaking(out, x, y);
+    }
     return out;
 }
+
+/*
+ * Histograms of the luma of the current frame, and of the luma and each color channel of the
+ * output, including any exposure warnings, focus peaking or debug colors drawn over it. Run
+ * over the output after the frame has been merged and tone mapped, with gHistogramCounts
+ * cleared. Output luma uses the BT.601 weights in 8-bit fixed point.
+ *
+ * This is a forEach kernel with atomic counters rather than a reduction kernel, since reductions
+ * need a RenderScript target API of 24 and the app runs from API 21.
+ */
+void __attribute__((kernel)) countHistograms(uchar4 out, uint32_t x, uint32_t y) {
+    uint frameLuma = (uint) min(readLuma(x, y), 255.f);
+    uint outputLuma = (77 * out.r + 150 * out.g + 29 * out.b + 128) >> 8;
+
+    rsAtomicInc(&gHistogramCounts[HISTOGRAM_FRAME_LUMA * HISTOGRAM_BINS + frameLuma]);
+    rsAtomicInc(&gHistogramCounts[HISTOGRAM_OUTPUT_LUMA * HISTOGRAM_BINS + outputLuma]);
+    rsAtomicInc(&gHistogramCounts[HISTOGRAM_RED * HISTOGRAM_BINS + out.r]);
+    rsAtomicInc(&gHistogramCounts[HISTOGRAM_GREEN * HISTOGRAM_BINS + out.g]);
+    rsAtomicInc(&gHistogramCounts[HISTOGRAM_BLUE * HISTOGRAM_BINS + out.b]);
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package com.example.android.hdrviewfinder;
+
+import static org.junit.Assert.assertEquals;
+import static org.junit.Assert.assertFalse;
+import static org.junit.Assert.assertTrue;
+
+import org.junit.Before;
+import org.junit.Test;
+
+/**
+ * Tests the exposures {@link AutoBracketController} picks for frames with known histograms. The
+ * expected steps follow from its targets: highlights at luma 230 with at most 0.5% of the
+ * pixels clipped, the darkest 10% at luma 80, a gamma of 2.2, steps of at most 2 stops, moving
+ * 0.3 of the way per frame and reporting changes of more than 0.25 stops.
+ */
+public class AutoBracketControllerTest {
+
+    private static final long MIN_EXPOSURE = 10000L;
+    private static final long MAX_EXPOSURE = 100000000L;
+    private static final long SHORT_EXPOSURE = 1000000L;
+    private static final long LONG_EXPOSURE = 16000000L;
+
+    private static final int PIXELS = 1000;
+
+    private AutoBracketController mController;
+
+    @Before
+    public void setUp() {
+        mController = new AutoBracketController(MIN_EXPOSURE, MAX_EXPOSURE);
+        mController.reset(SHORT_EXPOSURE, LONG_EXPOSURE);
+    }
+
+    @Test
+    public void holdsShortExposureWithHighlightsAtClipTarget() {
+        // 1% of the pixels at the highlight target puts the 99.5th percentile right on it
+        assertFalse(mController.update(frame(0, SHORT_EXPOSURE, 100, 990, 230, 10), 2));
+        assertEquals(SHORT_EXPOSURE, mController.getShortExposure());
+    }
+
+    @Test
+    public void stepsShortExposureDownWhenClipped() {
+        // More pixels clipped than allowed asks for the largest step down
+        assertTrue(mController.update(frame(0, SHORT_EXPOSURE, 100, 990, 255, 10), 2));
+        assertExposure(SHORT_EXPOSURE, 0.3 * -2, mController.getShortExposure());
+        assertEquals(LONG_EXPOSURE, mController.getLongExposure());
+    }
+
+    @Test
+    public void bringsShortExposureHighlightsToTarget() {
+        double step = 2.2 * log2(230. / 160.);
+        assertTrue(mController.update(frame(0, SHORT_EXPOSURE, 160, PIXELS), 2));
+        assertExposure(SHORT_EXPOSURE, 0.3 * step, mController.getShortExposure());
+    }
+
+    @Test
+    public void liftsLongExposureShadows() {
+        double step = 2.2 * log2(80. / 50.);
+        assertTrue(mController.update(frame(1, LONG_EXPOSURE, 50, 100, 200, 900), 2));
+        assertExposure(LONG_EXPOSURE, 0.3 * step, mController.getLongExposure());
+        assertEquals(SHORT_EXPOSURE, mController.getShortExposure());
+    }
+
+    @Test
+    public void keepsLongExposureAtLeastAsLongAsShort() {
+        mController.reset(SHORT_EXPOSURE, SHORT_EXPOSURE);
+        assertFalse(mController.update(frame(1, SHORT_EXPOSURE, 255, PIXELS), 2));
+        assertEquals(SHORT_EXPOSURE, mController.getLongExposure());
+    }
+
+    @Test
+    public void reportsSmoothedExposureOnlyPastHysteresis() {
+        // Each frame moves 0.3 of the way to a target 0.44 stops up, which only gets more than
+        // 0.25 stops away from the reported exposure on the third frame
+        double step = 2.2 * log2(230. / 200.);
+        assertFalse(mController.update(frame(0, SHORT_EXPOSURE, 200, PIXELS), 2));
+        assertFalse(mController.update(frame(0, SHORT_EXPOSURE, 200, PIXELS), 2));
+        assertEquals(SHORT_EXPOSURE, mController.getShortExposure());
+        assertTrue(mController.update(frame(0, SHORT_EXPOSURE, 200, PIXELS), 2));
+        assertExposure(SHORT_EXPOSURE, step * (1 - Math.pow(0.7, 3)),
+                mController.getShortExposure());
+    }
+
+    @Test
+    public void convergesWithoutOvershootOnFramesOfTheSameExposure() {
+        // Frames keep arriving with the exposure they were captured with until the camera
+        // switches to the new one; they all point at the same target, which is approached from
+        // below instead of being stepped past
+        double step = 2.2 * log2(230. / 160.);
+        for (int i = 0; i < 20; i++) {
+            mController.update(frame(0, SHORT_EXPOSURE, 160, PIXELS), 2);
+            double stops = log2((double) mController.getShortExposure() / SHORT_EXPOSURE);
+            assertTrue(stops <= step + 1e-6);
+        }
+        double stops = log2((double) mController.getShortExposure() / SHORT_EXPOSURE);
+        assertTrue(stops > step - 0.25);
+    }
+
+    @Test
+    public void ignoresMiddleAndUnknownFrames() {
+        assertFalse(mController.update(frame(1, SHORT_EXPOSURE, 255, PIXELS), 3));
+        assertFalse(mController.update(frame(-1, SHORT_EXPOSURE, 255, PIXELS), 2));
+        assertFalse(mController.update(frame(0, 0, 255, PIXELS), 2));
+        assertEquals(SHORT_EXPOSURE, mController.getShortExposure());
+        assertEquals(LONG_EXPOSURE, mController.getLongExposure());
+    }
+
+    /**
+     * Histograms of a frame with the given pairs of luma value and pixel count
+     */
+    private static ViewfinderProcessor.Histograms frame(int bracketIndex, long exposure,
+            int... lumaCounts) {
+        ViewfinderProcessor.Histograms histograms = new ViewfinderProcessor.Histograms();
+        histograms.bracketIndex = bracketIndex;
+        histograms.exposure = exposure;
+        for (int i = 0; i < lumaCounts.length; i += 2) {
+            histograms.frameLuma[lumaCounts[i]] = lumaCounts[i + 1];
+        }
+        return histograms;
+    }
+
+    private static void assertExposure(long exposure, double stops, long actual) {
+        assertEquals(exposure * Math.pow(2, stops), actual, 1.);
+    }
+
+    private static double log2(double value) {
+        return Math.log(value) / Math.log(2);
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+package com.example.android.hdrviewfinder;
+
+import static org.junit.Assert.assertArrayEquals;
+import static org.junit.Assert.assertEquals;
+import static org.junit.Assert.fail;
+
+import org.junit.Test;
+
+import java.io.ByteArrayInputStream;
+import java.io.IOException;
+
+/**
+ * Tests reading .cube files into a {@link CubeLut}, in particular the domain of input values the
+ * table spans.
+ */
+public class CubeLutTest {
+
+    // Identity table with 2 points along each axis
+    private static final String IDENTITY_POINTS =
+            "0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+    @Test
+    public void readsTableWithDefaultDomain() throws IOException {
+        CubeLut lut = read("TITLE \"Identity\"\nLUT_3D_SIZE 2\n" + IDENTITY_POINTS);
+        assertEquals(2, lut.size);
+        assertArrayEquals(new float[] {1.f, 0.f, 1.f, 1.f},
+                copyOfRange(lut.table, 5 * 4, 6 * 4), 0.f);
+        assertArrayEquals(new float[] {0.f, 0.f, 0.f}, lut.domainMin, 0.f);
+        assertArrayEquals(new float[] {1.f, 1.f, 1.f}, lut.domainMax, 0.f);
+    }
+
+    @Test
+    public void readsDomainPerChannel() throws IOException {
+        CubeLut lut = read("DOMAIN_MIN 0.1 0.2 0.3\nDOMAIN_MAX 0.9 0.8 0.7\nLUT_3D_SIZE 2\n"
+                + IDENTITY_POINTS);
+        assertArrayEquals(new float[] {0.1f, 0.2f, 0.3f}, lut.domainMin, 0.f);
+        assertArrayEquals(new float[] {0.9f, 0.8f, 0.7f}, lut.domainMax, 0.f);
+    }
+
+    @Test
+    public void readsInputRangeAsDomainOfEveryChannel() throws IOException {
+        CubeLut lut = read("LUT_3D_INPUT_RANGE 0.25 0.75\nLUT_3D_SIZE 2\n" + IDENTITY_POINTS);
+        assertArrayEquals(new float[] {0.25f, 0.25f, 0.25f}, lut.domainMin, 0.f);
+        assertArrayEquals(new float[] {0.75f, 0.75f, 0.75f}, lut.domainMax, 0.f);
+    }
+
+    @Test
+    public void rejectsEmptyInputRange() {
+        assertRejected("LUT_3D_INPUT_RANGE 1 1\nLUT_3D_SIZE 2\n" + IDENTITY_POINTS);
+        assertRejected("LUT_3D_INPUT_RANGE 0\nLUT_3D_SIZE 2\n" + IDENTITY_POINTS);
+    }
+
+    @Test
+    public void rejectsUnknownKeywords() {
+        assertRejected("LUT_3D_SIZE 2\nLUT_3D_SHAPER 0 1\n" + IDENTITY_POINTS);
+    }
+
+    @Test
+    public void rejectsOneDimensionalTables() {
+        assertRejected("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n");
+    }
+
+    @Test
+    public void rejectsMissingPoints() {
+        assertRejected("LUT_3D_SIZE 2\n0 0 0\n1 0 0\n");
+    }
+
+    private static CubeLut read(String contents) throws IOException {
+        return CubeLut.read(new ByteArrayInputStream(contents.getBytes("US-ASCII")));
+    }
+
+    private static void assertRejected(String contents) {
+        try {
+            read(contents);
+            fail("Expected an IOException");
+        } catch (IOException e) {
+            // expected
+        }
+    }
+
+    private static float[] copyOfRange(float[] values, int from, int to) {
+        float[] range = new float[to - from];
+        System.arraycopy(values, from, range, 0, range.length);
+        return range;
+    }
+}